@@ -0,0 +1,29 @@
+//! Steps the shaders and the CPU reference from the same start and prints how
+//! far apart every field is after each step.
+//!
+//! `cargo run --release --example parity -- [STEPS] [PARTICLES]`, set
+//! `WGPU_ADAPTER_NAME` to pick an adapter, e.g. `llvmpipe` for a software one.
+
+use particles::{parity, util, Config};
+
+fn main() {
+    let mut args = std::env::args().skip(1).map(|a| a.parse::<u32>().expect("expected whole numbers"));
+    let steps = args.next().unwrap_or(10);
+    let num_particles = args.next().unwrap_or(10000);
+
+    let (device, queue) = util::request_headless_device().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let config = Config { num_particles, map_fidelity: 256, ..Config::default() };
+    let results = parity::compare(&device, &queue, config, steps).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    println!("{:>6}  {:<16}{:>14}{:>14}", "step", "field", "max", "mean");
+    for parity in results {
+        print!("{}", parity);
+    }
+}
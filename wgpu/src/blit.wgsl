@@ -0,0 +1,27 @@
+struct VertexOutput {
+    @builtin(position) p: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var source : texture_2d<f32>;
+@group(0) @binding(1) var source_sampler : sampler;
+
+// A single triangle covering the whole screen, as in post.wgsl
+@vertex
+fn main_vs(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+
+    var output_val: VertexOutput;
+
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+
+    output_val.p = vec4<f32>(x, y, 0.0, 1.0);
+    output_val.uv = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return output_val;
+}
+
+// Stretches the source over the whole target
+@fragment
+fn main_fs(i: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, i.uv);
+}
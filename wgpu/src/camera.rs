@@ -0,0 +1,113 @@
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+// How much a single wheel notch zooms in or out
+const ZOOM_STEP: f32 = 1.1;
+
+// Keep the view from zooming into a single pixel or out into nothing
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 200.0;
+
+/// Pan and zoom applied to the rendered map, particles show through their trails.
+///
+/// A point `p` in clip space is displayed at `(p + offset) * scale`, the
+/// simulation itself never sees this transform.
+pub struct Camera {
+    offset: [f32; 2],
+    scale: f32,
+
+    // Window size in pixels, used to turn cursor positions into clip space
+    width: f32,
+    height: f32,
+
+    cursor: [f32; 2],
+    dragging: bool,
+    dirty: bool,
+}
+
+impl Camera {
+    pub fn new(width: u32, height: u32) -> Self {
+        Camera {
+            offset: [0.0, 0.0],
+            scale: 1.0,
+            width: width.max(1) as f32,
+            height: height.max(1) as f32,
+            cursor: [0.0, 0.0],
+            dragging: false,
+            dirty: true,
+        }
+    }
+
+    pub fn offset(&self) -> [f32; 2] {
+        self.offset
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// The point under the cursor before the view transform, i.e. in the space the map is drawn in
+    pub fn cursor_position(&self) -> [f32; 2] {
+        [
+            self.cursor[0] / self.scale - self.offset[0],
+            self.cursor[1] / self.scale - self.offset[1],
+        ]
+    }
+
+    /// Returns true once after every change, so the uniform is only rewritten when needed
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1) as f32;
+        self.height = height.max(1) as f32;
+    }
+
+    pub fn reset(&mut self) {
+        self.offset = [0.0, 0.0];
+        self.scale = 1.0;
+        self.dirty = true;
+    }
+
+    /// Handles mouse events, changes to the view show up in `take_dirty`
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = [
+                    (position.x as f32 / self.width) * 2.0 - 1.0,
+                    1.0 - (position.y as f32 / self.height) * 2.0,
+                ];
+                if self.dragging {
+                    self.offset[0] += (cursor[0] - self.cursor[0]) / self.scale;
+                    self.offset[1] += (cursor[1] - self.cursor[1]) / self.scale;
+                    self.dirty = true;
+                }
+                self.cursor = cursor;
+            }
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.dragging = *state == ElementState::Pressed;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let notches = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 50.0,
+                };
+                self.zoom(ZOOM_STEP.powf(notches));
+            }
+            _ => {}
+        }
+    }
+
+    // Zoom about the cursor, keeping the point under it fixed on screen
+    fn zoom(&mut self, factor: f32) {
+        let scale = (self.scale * factor).clamp(MIN_SCALE, MAX_SCALE);
+        for i in 0..2 {
+            let anchor = self.cursor[i] / self.scale - self.offset[i];
+            self.offset[i] = self.cursor[i] / scale - anchor;
+        }
+        self.scale = scale;
+        self.dirty = true;
+    }
+}
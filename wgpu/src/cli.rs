@@ -0,0 +1,289 @@
+use std::path::PathBuf;
+use std::process;
+use std::sync::OnceLock;
+
+use particles::map_init::MapInit;
+use particles::post;
+use particles::spawn::Spawn;
+use particles::sweep::Range;
+use particles::util::AdapterChoice;
+
+const USAGE: &str = "\
+usage: particles [options]
+
+  --headless            run without a window
+  --size WxH            size of headless and recorded frames (default 1024x1024)
+  --frames N            how many frames to run headless (default 1000)
+
+  --record FILE.png     record an animated PNG
+  --pipe COMMAND        stream raw RGBA frames to COMMAND's stdin, {width}, {height}
+                        and {fps} are substituted, e.g.
+                        \"ffmpeg -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - out.mp4\"
+  --fps N               recording frame rate (default 30)
+  --duration SECONDS    recording length (default 10)
+
+  --spawn PATTERN       where particles start: uniform, disc[:radius], ring[:radius,width],
+                        point[:x,y], clusters[:count,radius], gaussian[:sigma] or image:FILE.png
+                        to sample by pixel brightness (default disc:0.5)
+  --velocity DIR        initial motion: inward, outward, random or tangential (default inward)
+  --species FROM        preference of each particle: random, or the red, green or blue
+                        channel of the pixel it spawned on with image (default random)
+  --map-init FROM       what the trail map starts as: empty, uniform[:amplitude],
+                        perlin[:scale,amplitude], fbm[:scale,amplitude,octaves] or
+                        image:FILE.png[,amplitude] (default empty)
+  --seed N              random seed for the initial particles and map (default 43)
+  --deterministic       race free kernels so a seed gives bit identical runs on the same
+                        backend, slower. Headless runs print a hash of the result at the end
+  --lifecycle           agents eat trail for energy, starve when it runs out and divide when
+                        they have enough, not with --deterministic
+
+  --hdr                 draw into a floating point target with bloom and tone mapping, trails
+                        saturate at 4 instead of 1
+  --exposure E          brightness of the HDR image before tone mapping (default 1)
+  --bloom-threshold T   brightness above which pixels bloom (default 0.8)
+  --bloom-strength S    how much of the bloom is added back over the image (default 0.6)
+  --tone-mapping CURVE  clamp, reinhard or aces, T cycles them at runtime (default aces).
+                        These four turn on --hdr
+
+  --export-dir DIR      where NumPy snapshots go, E saves one at any time (default export)
+  --export-every N      also save a snapshot every N simulation steps
+  --npz                 bundle each snapshot into a single .npz instead of two .npy
+
+  --metrics FILE.csv    write statistics of the map and particles as a time series
+  --metrics-every N     simulation steps between rows of --metrics (default 1)
+
+  --network-threshold T trail a cell needs to be part of the network G saves as GraphML and
+                        JSON into the export directory (default 0.2)
+  --food X,Y            a food source in simulation coordinates, the network summary reports
+                        how many pairs of them are connected. Can be given more than once
+
+  --sweep NAME=RANGE    run off screen once for every combination of the swept parameters
+                        instead of opening a window, RANGE is FROM:TO:COUNT or A,B,C and
+                        NAME any parameter of Config, e.g. sense_force=0.1:0.5:5. Can be
+                        given more than once
+  --sweep-steps N       simulation steps of each run of a sweep (default 1000)
+  --sweep-dir DIR       where a sweep writes each run, sweep.csv and the contact sheet
+                        (default sweep)
+
+  --list-adapters       print every adapter with its features and limits, and whether it
+                        can run the simulation, then exit
+  --backend LIST        backends to look for adapters on, comma separated from vulkan, dx12,
+                        dx11, metal and gl (default WGPU_BACKEND or all of them)
+  --adapter NAME        run on the adapter whose name contains NAME, or on the software
+                        adapter with fallback (default WGPU_ADAPTER_NAME or the system's
+                        default, falling back to software if that can't run it)
+
+  --watch-shaders [DIR] recompile the pipelines whenever a .wgsl file in DIR changes, errors
+                        are printed and the previous pipeline kept (default the src
+                        directory this binary was built from)
+";
+
+/// Where recorded frames end up
+#[derive(Clone, Debug)]
+pub enum RecordOutput {
+    Apng(PathBuf),
+    Pipe(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub output: RecordOutput,
+    pub fps: u32,
+    pub frames: u32,
+}
+
+/// Command line options, anything not given keeps the defaults at the top of main.rs
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub headless: bool,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub record: Option<Record>,
+    pub spawn: Spawn,
+    pub map_init: MapInit,
+    pub seed: u64,
+    pub deterministic: bool,
+    pub lifecycle: bool,
+    pub hdr: bool,
+    pub post: post::Settings,
+    pub export_dir: PathBuf,
+    pub export_every: u32,
+    pub npz: bool,
+    pub metrics: Option<PathBuf>,
+    pub metrics_every: u32,
+    pub network_threshold: f32,
+    pub food: Vec<[f32; 2]>,
+    pub sweep: Vec<Range>,
+    pub sweep_steps: u32,
+    pub sweep_dir: PathBuf,
+    pub list_adapters: bool,
+    pub adapter: AdapterChoice,
+    pub watch_shaders: Option<PathBuf>,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> T where T::Err: std::fmt::Display {
+    let value = value.unwrap_or_else(|| fail(&format!("{} needs a value", flag)));
+    value.parse().unwrap_or_else(|e| fail(&format!("invalid value for {}: {} ({})", flag, value, e)))
+}
+
+impl Options {
+
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Options {
+
+        let mut args = args.peekable();
+
+        let mut options = Options {
+            headless: false,
+            width: 1024,
+            height: 1024,
+            frames: 1000,
+            record: None,
+            spawn: Spawn::default(),
+            map_init: MapInit::default(),
+            seed: 43,
+            deterministic: false,
+            lifecycle: false,
+            hdr: false,
+            post: post::Settings::default(),
+            export_dir: PathBuf::from("export"),
+            export_every: 0,
+            npz: false,
+            metrics: None,
+            metrics_every: 1,
+            network_threshold: 0.2,
+            food: Vec::new(),
+            sweep: Vec::new(),
+            sweep_steps: 1000,
+            sweep_dir: PathBuf::from("sweep"),
+            list_adapters: false,
+            adapter: AdapterChoice::default(),
+            watch_shaders: None,
+        };
+
+        let mut output = None;
+        let mut fps = 30;
+        let mut duration = 10.0f32;
+        let mut frames = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--size" => {
+                    let size: String = value(&arg, args.next());
+                    let (w, h) = size.split_once('x').unwrap_or_else(|| fail("--size expects WxH"));
+                    options.width = value("--size", Some(w.to_string()));
+                    options.height = value("--size", Some(h.to_string()));
+                }
+                "--frames" => frames = Some(value(&arg, args.next())),
+                "--record" => output = Some(RecordOutput::Apng(value(&arg, args.next()))),
+                "--pipe" => output = Some(RecordOutput::Pipe(value(&arg, args.next()))),
+                "--fps" => fps = value(&arg, args.next()),
+                "--duration" => duration = value(&arg, args.next()),
+                "--spawn" => options.spawn.pattern = value(&arg, args.next()),
+                "--velocity" => options.spawn.velocity = value(&arg, args.next()),
+                "--species" => options.spawn.species = value(&arg, args.next()),
+                "--map-init" => options.map_init = value(&arg, args.next()),
+                "--seed" => options.seed = value(&arg, args.next()),
+                "--deterministic" => options.deterministic = true,
+                "--lifecycle" => options.lifecycle = true,
+                "--hdr" => options.hdr = true,
+                "--exposure" => {
+                    options.post.exposure = value(&arg, args.next());
+                    options.hdr = true;
+                }
+                "--bloom-threshold" => {
+                    options.post.bloom_threshold = value(&arg, args.next());
+                    options.hdr = true;
+                }
+                "--bloom-strength" => {
+                    options.post.bloom_strength = value(&arg, args.next());
+                    options.hdr = true;
+                }
+                "--tone-mapping" => {
+                    options.post.tone_mapping = value(&arg, args.next());
+                    options.hdr = true;
+                }
+                "--export-dir" => options.export_dir = value(&arg, args.next()),
+                "--export-every" => options.export_every = value(&arg, args.next()),
+                "--npz" => options.npz = true,
+                "--metrics" => options.metrics = Some(value(&arg, args.next())),
+                "--metrics-every" => options.metrics_every = value(&arg, args.next()),
+                "--network-threshold" => options.network_threshold = value(&arg, args.next()),
+                "--food" => {
+                    let food: String = value(&arg, args.next());
+                    let (x, y) = food.split_once(',').unwrap_or_else(|| fail("--food expects X,Y"));
+                    options.food.push([value("--food", Some(x.to_string())), value("--food", Some(y.to_string()))]);
+                }
+                "--sweep" => options.sweep.push(value(&arg, args.next())),
+                "--sweep-steps" => options.sweep_steps = value(&arg, args.next()),
+                "--sweep-dir" => options.sweep_dir = value(&arg, args.next()),
+                "--list-adapters" => options.list_adapters = true,
+                "--backend" => {
+                    let list: String = value(&arg, args.next());
+                    // Unknown names would otherwise be dropped with only a log warning
+                    let mut backends = wgpu::Backends::empty();
+                    for name in list.split(',') {
+                        let backend = wgpu::util::parse_backends_from_comma_list(name);
+                        if backend.is_empty() {
+                            fail(&format!("unknown backend {}", name));
+                        }
+                        backends |= backend;
+                    }
+                    options.adapter.backends = Some(backends);
+                }
+                "--adapter" => {
+                    let name: String = value(&arg, args.next());
+                    if name.eq_ignore_ascii_case("fallback") {
+                        options.adapter.fallback = true;
+                    } else {
+                        options.adapter.name = Some(name);
+                    }
+                }
+                "--watch-shaders" => {
+                    // The directory is optional, anything that isn't another flag is taken as it
+                    let dir = match args.peek() {
+                        Some(next) if !next.starts_with("--") => PathBuf::from(args.next().unwrap()),
+                        _ => PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src")),
+                    };
+                    options.watch_shaders = Some(dir);
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => fail(&format!("unknown option {}", arg)),
+            }
+        }
+
+        if fps == 0 {
+            fail("--fps must be at least 1");
+        }
+        if options.metrics_every == 0 {
+            fail("--metrics-every must be at least 1");
+        }
+
+        // A recording decides how long a headless run lasts unless told otherwise
+        if let Some(output) = output {
+            let recorded = ((duration * fps as f32).round() as u32).max(1);
+            options.record = Some(Record { output, fps, frames: recorded });
+            options.frames = recorded;
+        }
+        if let Some(frames) = frames {
+            options.frames = frames;
+        }
+
+        options
+    }
+}
+
+/// Options of this process, parsed from the command line on first use
+pub fn options() -> &'static Options {
+    static OPTIONS: OnceLock<Options> = OnceLock::new();
+    OPTIONS.get_or_init(|| Options::parse(std::env::args().skip(1)))
+}
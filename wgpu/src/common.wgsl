@@ -0,0 +1,55 @@
+// Shared by every simulation shader, `shader!` in util.rs prepends this file
+// so the structs below are declared once. Keep them in step with constants.rs
+// and particle.rs.
+
+// Matches `SimulationConstants` in constants.rs
+struct SimulationConstants {
+    simulationSpeed : f32,
+    mapBounds : f32,
+    oobForce : f32,
+    maxSpeed : f32,
+    minSpeed : f32,
+    // At most `MAX_MAP_FIDELITY` from config.rs, so the cell count is exact in f32
+    mapFidelity : f32,
+    cellImpact : f32,
+    senseDistance : f32,
+    senseForce : f32,
+    fadePower : f32,
+    erasePower : f32,
+    instabilityScore : f32,
+    trailCeiling : f32,
+    numParticles : u32,
+    respawnChance : f32,
+    maxParticles : u32,
+    energyDecay : f32,
+    energyGain : f32,
+    reproduceEnergy : f32,
+};
+
+// Matches `Particle` in particle.rs, all f32 so the stride is 28 bytes
+struct Particle {
+    posX : f32,
+    posY : f32,
+    senseDistance : f32,
+    velX : f32,
+    velY : f32,
+    preference : f32,
+    energy : f32,
+};
+
+// Invocations per workgroup, `WORKGROUP_SIZE` in util.rs. `@workgroup_size`
+// only takes a literal, tests/shaders.rs checks every kernel's against this
+let WORKGROUP_SIZE = 64u;
+
+// Flat index of an invocation in a dispatch split into rows of workgroups by
+// `dispatch_grid` in util.rs, `groups` is the `num_workgroups` builtin
+fn invocation_index(id : vec3<u32>, groups : vec3<u32>) -> u32 {
+    return id.x + id.y * groups.x * WORKGROUP_SIZE;
+}
+
+// Pan and zoom of the render shaders, a point `p` is drawn at `(p + offset) * scale`
+struct ViewTransform {
+    offset : vec2<f32>,
+    scale : f32,
+    _padding : f32,
+};
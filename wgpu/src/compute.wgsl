@@ -1,22 +1,9 @@
-struct SimulationConstants {
-    simulationSpeed : f32,
-    mapBounds : f32,
-    oobForce : f32,
-    maxSpeed : f32,
-    minSpeed : f32,
-    mapFidelity : f32,
-    cellImpact : f32,
-    senseDistance : f32,
-    senseForce : f32,
-    fadePower : f32,
-    erasePower : f32,
-    instabilityScore : f32,
-};
-
 @group(0) @binding(0) var<uniform> consts : SimulationConstants;
-@group(1) @binding(0) var<storage, read_write> particles : array<f32>;
+@group(1) @binding(0) var<storage, read_write> particles : array<Particle>;
 @group(2) @binding(0) var<storage, read_write> map : array<f32>;
 @group(3) @binding(0) var<storage, read_write> indicators : array<f32>;
+// Deterministic mode only, how many particles landed in each cell this step
+@group(3) @binding(1) var<storage, read_write> deposits : array<atomic<u32>>;
 
 fn get_cell_index (x : f32, y : f32) -> i32 {
 
@@ -92,33 +79,47 @@ fn choose_one ( a : vec3<f32>, b : vec3<f32>, c : vec3<f32>, instability : f32 )
 
 }
 
-@compute
-@workgroup_size(64)
-fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+// PCG hash, turns any u32 into a well mixed one
+fn hash ( x : u32 ) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Uniform float in [0, 1)
+fn random ( x : u32 ) -> f32 {
+    return f32(hash(x) >> 8u) / 16777216.0;
+}
+
+// Moves one particle, returning the cell it deposits into or -1 if the slot is empty
+fn move_particle(index : u32) -> i32 {
 
     // Construct particle stuff
 
-    let index = global_invocation_id.x;
+    // Dispatches round up to whole workgroups and slots past the live count
+    // are spare capacity, neither holds a particle
+    if (index >= consts.numParticles) {
+        return -1;
+    }
 
-    var _1 = u32(1);
-    var _2 = u32(2);
-    var _3 = u32(3);
+    var particle = particles[index];
 
-    var i_p = index * u32(6);
-    var i_v = i_p + _3;
+    var _dist = particle.senseDistance;
+    var _target = particle.preference;
 
-    var _dist = particles[i_p+_2];
-    var _target = particles[i_p+_3+_2];
+    var pos = vec2( particle.posX, particle.posY );
+    var vel = vec2( particle.velX, particle.velY );
 
-    var pos = vec2(
-        particles[i_p],
-        particles[i_p+_1]
-    );
+    // Death and respawn, agents that went non finite always respawn. The
+    // particle's own state seeds the dice so no extra buffer is needed
 
-    var vel = vec2(
-        particles[i_v],
-        particles[i_v+_1]
-    );
+    let seed = hash(index ^ hash(bitcast<u32>(pos.x) ^ hash(bitcast<u32>(pos.y))));
+    let alive = abs(pos.x) <= 2.0 && abs(pos.y) <= 2.0 && abs(vel.x) <= 2.0 && abs(vel.y) <= 2.0;
+    if ( !alive || random(seed) < consts.respawnChance ) {
+        let direction = random(seed + 3u) * 6.283185;
+        pos = vec2( random(seed + 1u) * 2.0 - 1.0, random(seed + 2u) * 2.0 - 1.0 );
+        vel = vec2( cos(direction), sin(direction) ) * consts.maxSpeed;
+    }
 
     // Guard checking
 
@@ -141,10 +142,11 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     // // If oob, end here
     // var in_bounds = pos.x > -1.0 && pos.x < 1.0 && pos.y > -1.0 && pos.y < 1.0;
     // if ( !in_bounds ) {
-    //     particles[i_p] = pos.x;
-    //     particles[i_p+_1] = pos.y;
-    //     particles[i_v] = vel.x;
-    //     particles[i_v+_1] = vel.y;
+    //     particle.posX = pos.x;
+    //     particle.posY = pos.y;
+    //     particle.velX = vel.x;
+    //     particle.velY = vel.y;
+    //     particles[index] = particle;
     //     return;
     // }
 
@@ -182,19 +184,44 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     // Do Movement
     pos = pos + vel;
 
-    // Save new position
-    particles[i_p] = pos.x;
-    particles[i_p+_1] = pos.y;
+    // Save new position and velocity
+    particle.posX = pos.x;
+    particle.posY = pos.y;
+    particle.velX = vel.x;
+    particle.velY = vel.y;
+    particles[index] = particle;
+
+    return get_cell_index(pos.x, pos.y);
+}
+
+@compute
+@workgroup_size(64)
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
 
-    // Save new velocity
-    particles[i_v] = vel.x;
-    particles[i_v+_1] = vel.y;
+    let i = move_particle(invocation_index(global_invocation_id, num_workgroups));
+    if (i < 0) {
+        return;
+    }
 
     // Update the map
-    let i = get_cell_index(pos.x, pos.y);
     map[i] += consts.cellImpact;
-    if (map[i] >= 1.0) {
-        map[i] = 1.0;
+    if (map[i] >= consts.trailCeiling) {
+        map[i] = consts.trailCeiling;
     }
         
 }
+
+// Deterministic mode, the map is only read here while particles move. Deposits
+// are counted instead and added by `apply_deposits` in compute_map.wgsl, an
+// integer count comes out the same whichever order the particles run in
+@compute
+@workgroup_size(64)
+fn main_deterministic(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+
+    let i = move_particle(invocation_index(global_invocation_id, num_workgroups));
+    if (i < 0) {
+        return;
+    }
+
+    atomicAdd(&deposits[i], 1u);
+}
@@ -0,0 +1,101 @@
+struct Counter {
+    next : atomic<u32>,
+    count : u32,
+};
+
+@group(0) @binding(0) var<uniform> consts : SimulationConstants;
+@group(1) @binding(0) var<storage, read> particles_in : array<Particle>;
+@group(2) @binding(0) var<storage, read_write> particles_out : array<Particle>;
+@group(2) @binding(1) var<storage, read_write> counter : Counter;
+@group(3) @binding(0) var<storage, read_write> map : array<f32>;
+
+fn get_cell_index (x : f32, y : f32) -> i32 {
+
+    let world_x = ( x + 1.0) / 2.0 * consts.mapFidelity;
+    let world_y = ( -y + 1.0) / 2.0 * consts.mapFidelity;
+
+    var index_x = floor( world_x );
+    var index_y = floor( world_y );
+
+    if (index_x < 0.0 ) { index_x = index_x + consts.mapFidelity; }
+    if (index_y < 0.0 ) { index_y = index_y + consts.mapFidelity; }
+    if (index_y > consts.mapFidelity - 1.0 ) { index_y = index_y - consts.mapFidelity; }
+    if (index_x > consts.mapFidelity - 1.0 ) { index_x = index_x - consts.mapFidelity; }
+    
+    return i32(index_y * consts.mapFidelity + index_x);
+}
+
+// PCG hash, turns any u32 into a well mixed one
+fn hash ( x : u32 ) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Uniform float in [0, 1)
+fn random ( x : u32 ) -> f32 {
+    return f32(hash(x) >> 8u) / 16777216.0;
+}
+
+// Feeds every live agent, drops the starved ones and splits the well fed
+// ones, packing the survivors and their children into the front of the
+// output buffers
+@compute
+@workgroup_size(64)
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+
+    let index = invocation_index(global_invocation_id, num_workgroups);
+    if (index >= consts.numParticles) {
+        return;
+    }
+
+    var particle = particles_in[index];
+    let pos = vec2( particle.posX, particle.posY );
+
+    // Eat some of the trail under the agent
+    let cell = get_cell_index(pos.x, pos.y);
+    let eaten = clamp(map[cell], 0.0, consts.energyGain);
+    map[cell] = map[cell] - eaten;
+
+    particle.energy = particle.energy + eaten - consts.energyDecay;
+    if (particle.energy <= 0.0) {
+        return;
+    }
+
+    // A dividing agent reserves its own slot and its child's together, the
+    // parent first. Slots past the end are dropped, and a parent whose child
+    // didn't fit keeps all of its energy
+    let divides = particle.energy >= consts.reproduceEnergy;
+    let slot = atomicAdd(&counter.next, select(1u, 2u, divides));
+    if (slot >= consts.maxParticles) {
+        return;
+    }
+
+    let child = slot + 1u;
+    let has_child = divides && child < consts.maxParticles;
+    if (has_child) {
+        particle.energy = particle.energy * 0.5;
+    }
+
+    particles_out[slot] = particle;
+
+    // The child turns away from its parent
+    if (has_child) {
+        let turn = (random(hash(index) ^ bitcast<u32>(pos.x)) - 0.5) * 3.141592;
+        let vx = particle.velX;
+        let vy = particle.velY;
+        particle.velX = vx * cos(turn) - vy * sin(turn);
+        particle.velY = vx * sin(turn) + vy * cos(turn);
+        particles_out[child] = particle;
+    }
+}
+
+// Publishes the new population and rearms the counter for the next step,
+// run as a single invocation once `main` is done
+@compute
+@workgroup_size(1)
+fn finish() {
+    let next = atomicLoad(&counter.next);
+    counter.count = min(next, consts.maxParticles);
+    atomicStore(&counter.next, 0u);
+}
@@ -1,22 +1,10 @@
 
-struct SimulationConstants {
-    simulationSpeed : f32,
-    mapBounds : f32,
-    oobForce : f32,
-    maxSpeed : f32,
-    minSpeed : f32,
-    mapFidelity : f32,
-    cellImpact : f32,
-    senseDistance : f32,
-    senseForce : f32,
-    fadePower : f32,
-    erasePower : f32,
-    instabilityScore : f32,
-};
-
 @group(0) @binding(0) var<uniform> consts : SimulationConstants;
 @group(1) @binding(0) var<storage, read_write> map : array<f32>;
 @group(2) @binding(0) var<storage, read_write> indicator : array<f32>;
+// Deterministic mode only, the map being written and the deposits of compute.wgsl
+@group(3) @binding(0) var<storage, read_write> next_map : array<f32>;
+@group(3) @binding(1) var<storage, read_write> deposits : array<atomic<u32>>;
 
 fn who_cell (x : i32, y : i32) -> i32 {
     
@@ -38,9 +26,9 @@ fn read_cell ( x : i32, y : i32) -> f32 {
 
 @compute
 @workgroup_size(64)
-fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
 
-    let index = global_invocation_id.x;
+    let index = invocation_index(global_invocation_id, num_workgroups);
     if (index >= u32(consts.mapFidelity * consts.mapFidelity)) {
         return;
     }
@@ -74,8 +62,8 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
 
     map[index] = map[index] + (_take_left + _take_right + _take_top + _take_bottom) * 0.6;
 
-    if (map[index] > 1.0) {
-        map[index] = 1.0;
+    if (map[index] > consts.trailCeiling) {
+        map[index] = consts.trailCeiling;
     }
     if (map[index] < 0.00001) {
         map[index] = 0.0;
@@ -83,4 +71,53 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
 
 }
 
+// Deterministic mode, each cell gathers from the previous map into `next_map`
+// rather than taking from its neighbours in place. The result is `main` as if
+// its cells took turns: erased, then taken from by each neighbour, then given
+// its share of what it took from them
+@compute
+@workgroup_size(64)
+fn diffuse(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+
+    let index = invocation_index(global_invocation_id, num_workgroups);
+    if (index >= u32(consts.mapFidelity * consts.mapFidelity)) {
+        return;
+    }
+
+    var grid_size = consts.mapFidelity;
+    let cell_x = i32(i32(index) % i32(grid_size));
+    let cell_y = i32(i32(index) / i32(grid_size));
+
+    let kept = 1.0 - consts.fadePower;
+    let center = consts.erasePower * read_cell(cell_x, cell_y) * kept * kept * kept * kept;
+
+    let around = read_cell(cell_x - 1, cell_y) + read_cell(cell_x + 1, cell_y) + read_cell(cell_x, cell_y - 1) + read_cell(cell_x, cell_y + 1);
+    var value = center + around * consts.fadePower * 0.6;
+
+    if (value > consts.trailCeiling) {
+        value = consts.trailCeiling;
+    }
+    if (value < 0.00001) {
+        value = 0.0;
+    }
+
+    next_map[index] = value;
+    indicator[index] = 0.0;
+}
+
+// Deterministic mode, adds the deposits counted by `main_deterministic` and
+// empties them for the next step
+@compute
+@workgroup_size(64)
+fn apply_deposits(@builtin(global_invocation_id) global_invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+
+    let index = invocation_index(global_invocation_id, num_workgroups);
+    if (index >= u32(consts.mapFidelity * consts.mapFidelity)) {
+        return;
+    }
 
+    let count = atomicExchange(&deposits[index], 0u);
+    if (count > 0u) {
+        map[index] = min(map[index] + f32(count) * consts.cellImpact, consts.trailCeiling);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::constants::SimulationConstants;
+use crate::map_init::MapInit;
+use crate::particle::Particle;
+use crate::spawn::Spawn;
+use crate::util::WORKGROUP_SIZE;
+
+/// Largest `map_fidelity` a simulation runs with. The shaders index the map in
+/// f32, which counts cells exactly only up to 2^24, a 4096x4096 map
+pub const MAX_MAP_FIDELITY: u32 = 4096;
+
+/// Everything that controls a simulation run.
+///
+/// `max_particles` and `map_fidelity` size the GPU buffers, `lifecycle` and
+/// `deterministic` add buffers and `target_format` sizes the render pipelines, these are fixed once
+/// a simulation is created, the rest
+/// can be changed between steps, `num_particles` anywhere up to the capacity. `spawn`, `map_init` and `seed`
+/// take effect on the next reset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+
+    // Particle count, how many of the `capacity` slots are alive
+    pub num_particles: u32,
+
+    // Particles the buffers have room for, never below `num_particles`
+    pub max_particles: u32,
+
+    // How many regions n x n we want to split the screen into
+    pub map_fidelity: u32,
+
+    // Used to calculate step sizes
+    pub simulation_speed: f32,
+
+    // Where "OOB" starts and how hard to push them back in
+    // DEAD
+    pub map_boundery: f32,
+    // DEAD
+    pub oob_force: f32,
+
+    // Max speed moved in 1.0 time step
+    pub max_speed: f32,
+    pub min_speed: f32,
+
+    // How much the map changes
+    pub cell_impact: f32,
+
+    // How powerful is the senses
+    // DEAD
+    pub sense_distance: f32,
+    pub sense_force: f32,
+
+    // How powerful the fade is
+    pub fade_power: f32,
+    pub erase_power: f32,
+
+    // How stable it should be 1 is perfect
+    // DEAD
+    pub instablity: f32,
+
+    // Where trails saturate, only values up to 1.0 can be shown without HDR
+    pub trail_ceiling: f32,
+
+    // Chance per step that an agent dies and respawns somewhere random
+    pub respawn_chance: f32,
+
+    // Agents eat trail for energy, starve when it runs out and divide when they
+    // have enough, changing the population on the GPU. Fixed at creation
+    pub lifecycle: bool,
+    // Energy lost every step, most trail eaten in a step and the energy needed
+    // to divide, the two halves each keep half
+    pub energy_decay: f32,
+    pub energy_gain: f32,
+    pub reproduce_energy: f32,
+
+    // Race free kernels, so a seed and config give the same bits on every run
+    // on the same backend. Slower, not with `lifecycle`. Fixed at creation
+    pub deterministic: bool,
+
+    // Where particles start, which way they move and what the map starts as, drawn from `seed`
+    pub spawn: Spawn,
+    pub map_init: MapInit,
+    pub seed: u64,
+
+    // Format of the views `Simulation::render` draws into
+    pub target_format: wgpu::TextureFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            num_particles: 3000000,
+            max_particles: 0,
+            map_fidelity: 500,
+            simulation_speed: 1.0,
+            map_boundery: 0.85,
+            oob_force: 0.003,
+            max_speed: 0.01,
+            min_speed: 0.0002,
+            cell_impact: 0.001,
+            sense_distance: 0.04,
+            sense_force: 0.4,
+            fade_power: 0.009,
+            erase_power: 0.99,
+            instablity: 0.0,
+            trail_ceiling: 1.0,
+            respawn_chance: 0.0,
+            lifecycle: false,
+            energy_decay: 0.0005,
+            energy_gain: 0.002,
+            reproduce_energy: 1.0,
+            deterministic: false,
+            spawn: Spawn::default(),
+            map_init: MapInit::default(),
+            seed: 43,
+            target_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        }
+    }
+}
+
+impl Config {
+
+    /// Names accepted by `set`, in the order they appear in the struct
+    pub const PARAMS: [&'static str; 22] = [
+        "num_particles",
+        "max_particles",
+        "map_fidelity",
+        "simulation_speed",
+        "map_boundery",
+        "oob_force",
+        "max_speed",
+        "min_speed",
+        "cell_impact",
+        "sense_distance",
+        "sense_force",
+        "fade_power",
+        "erase_power",
+        "instablity",
+        "trail_ceiling",
+        "respawn_chance",
+        "lifecycle",
+        "energy_decay",
+        "energy_gain",
+        "reproduce_energy",
+        "deterministic",
+        "seed",
+    ];
+
+    /// Sets a parameter by name, as used from the command line and Python
+    pub fn set(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let value_f32 = value as f32;
+        match name {
+            "num_particles" | "map_fidelity" if value < 1.0 || value.fract() != 0.0 => {
+                return Err(format!("{} must be a positive whole number, got {}", name, value));
+            }
+            "num_particles" | "max_particles" if value > u32::MAX as f64 => {
+                return Err(format!("{} can be at most {}, got {}", name, u32::MAX, value));
+            }
+            "max_particles" | "seed" if value < 0.0 || value.fract() != 0.0 => {
+                return Err(format!("{} must be a whole number, got {}", name, value));
+            }
+            "num_particles" => self.num_particles = value as u32,
+            "max_particles" => self.max_particles = value as u32,
+            "map_fidelity" => self.map_fidelity = value as u32,
+            "simulation_speed" => self.simulation_speed = value_f32,
+            "map_boundery" => self.map_boundery = value_f32,
+            "oob_force" => self.oob_force = value_f32,
+            "max_speed" => self.max_speed = value_f32,
+            "min_speed" => self.min_speed = value_f32,
+            "cell_impact" => self.cell_impact = value_f32,
+            "sense_distance" => self.sense_distance = value_f32,
+            "sense_force" => self.sense_force = value_f32,
+            "fade_power" => self.fade_power = value_f32,
+            "erase_power" => self.erase_power = value_f32,
+            "instablity" => self.instablity = value_f32,
+            "trail_ceiling" => self.trail_ceiling = value_f32,
+            "respawn_chance" => self.respawn_chance = value_f32,
+            "lifecycle" => self.lifecycle = value != 0.0,
+            "energy_decay" => self.energy_decay = value_f32,
+            "energy_gain" => self.energy_gain = value_f32,
+            "reproduce_energy" => self.reproduce_energy = value_f32,
+            "deterministic" => self.deterministic = value != 0.0,
+            "seed" => self.seed = value as u64,
+            _ => return Err(format!("unknown parameter {}, expected one of {}", name, Config::PARAMS.join(", "))),
+        }
+        Ok(())
+    }
+
+    /// Reads a parameter by name, the counterpart of `set`
+    pub fn get(&self, name: &str) -> Option<f64> {
+        Some(match name {
+            "num_particles" => self.num_particles as f64,
+            "max_particles" => self.max_particles as f64,
+            "map_fidelity" => self.map_fidelity as f64,
+            "simulation_speed" => self.simulation_speed as f64,
+            "map_boundery" => self.map_boundery as f64,
+            "oob_force" => self.oob_force as f64,
+            "max_speed" => self.max_speed as f64,
+            "min_speed" => self.min_speed as f64,
+            "cell_impact" => self.cell_impact as f64,
+            "sense_distance" => self.sense_distance as f64,
+            "sense_force" => self.sense_force as f64,
+            "fade_power" => self.fade_power as f64,
+            "erase_power" => self.erase_power as f64,
+            "instablity" => self.instablity as f64,
+            "trail_ceiling" => self.trail_ceiling as f64,
+            "respawn_chance" => self.respawn_chance as f64,
+            "lifecycle" => self.lifecycle as u8 as f64,
+            "energy_decay" => self.energy_decay as f64,
+            "energy_gain" => self.energy_gain as f64,
+            "reproduce_energy" => self.reproduce_energy as f64,
+            "deterministic" => self.deterministic as u8 as f64,
+            "seed" => self.seed as f64,
+            _ => return None,
+        })
+    }
+
+    /// Rejects settings that can't run together, `Simulation::new` checks this
+    /// before touching the device
+    pub fn validate(&self) -> Result<(), String> {
+        // Compaction hands out slots in whatever order agents finish
+        if self.deterministic && self.lifecycle {
+            return Err("lifecycle runs can't be deterministic".to_string());
+        }
+        if self.map_fidelity > MAX_MAP_FIDELITY {
+            return Err(format!("map_fidelity can be at most {}, not {}", MAX_MAP_FIDELITY, self.map_fidelity));
+        }
+        Ok(())
+    }
+
+    /// Particle slots to allocate, `max_particles` unless it is below `num_particles`
+    pub fn capacity(&self) -> u32 {
+        self.max_particles.max(self.num_particles)
+    }
+
+    /// Lowers the capacity and map fidelity to what a device with `limits` can
+    /// bind and dispatch, returning a line for each change. The particles and
+    /// the map are a single storage binding each
+    pub fn fit(&mut self, limits: &wgpu::Limits) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        // Rows of workgroups as wide as the device allows, see `util::dispatch_grid`
+        let dispatchable = limits.max_compute_workgroups_per_dimension as u64 * limits.max_compute_workgroups_per_dimension as u64 * WORKGROUP_SIZE as u64;
+        let fits = |bytes_each: usize| (limits.max_storage_buffer_binding_size as u64 / bytes_each as u64).min(dispatchable).min(u32::MAX as u64) as u32;
+
+        let particles = fits(Particle::SIZE);
+        if self.capacity() > particles {
+            changes.push(format!("can only hold {} particles, lowering from {}", particles, self.capacity()));
+            self.max_particles = particles;
+            self.num_particles = self.num_particles.min(particles);
+        }
+
+        let fidelity = ((fits(std::mem::size_of::<f32>()) as f64).sqrt() as u32).min(MAX_MAP_FIDELITY);
+        if self.map_fidelity > fidelity {
+            changes.push(format!("can only hold a {0}x{0} map, lowering from {1}x{1}", fidelity, self.map_fidelity));
+            self.map_fidelity = fidelity;
+        }
+
+        changes
+    }
+
+    /// The uniform shared with every shader
+    pub fn constants(&self) -> SimulationConstants {
+        SimulationConstants {
+            simulation_speed: self.simulation_speed,
+            map_bounds: self.map_boundery,
+            oob_force: self.oob_force,
+            max_speed: self.max_speed,
+            min_speed: self.min_speed,
+            map_fidelity: self.map_fidelity as f32,
+            cell_impact: self.cell_impact,
+            sense_distance: self.sense_distance,
+            sense_force: self.sense_force,
+            fade_power: self.fade_power,
+            erase_power: self.erase_power,
+            instability_score: self.instablity,
+            trail_ceiling: self.trail_ceiling,
+            num_particles: self.num_particles,
+            respawn_chance: self.respawn_chance,
+            max_particles: self.max_particles,
+            energy_decay: self.energy_decay,
+            energy_gain: self.energy_gain,
+            reproduce_energy: self.reproduce_energy,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Declares the struct and lists its fields, so the list can't fall behind it
+macro_rules! constants {
+    ($(#[$meta:meta])* pub struct $name:ident { $( $field:ident : $ty:ident ),* $(,)? }) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+        pub struct $name {
+            $( pub $field: $ty, )*
+        }
+
+        impl $name {
+            /// Every field's name and type in order, tests/shaders.rs checks
+            /// `common.wgsl` against them
+            #[doc(hidden)]
+            pub const MEMBERS: &'static [(&'static str, &'static str)] = &[ $( (stringify!($field), stringify!($ty)), )* ];
+        }
+    };
+}
+
+constants! {
+    /// The uniform every simulation shader reads, laid out to match
+    /// `SimulationConstants` in `common.wgsl`.
+    ///
+    /// Built by `Config::constants`. Every field is 4 bytes wide, so the struct
+    /// has no padding and adding one means adding it here, in `common.wgsl` and
+    /// nowhere else.
+    pub struct SimulationConstants {
+        simulation_speed: f32,
+        map_bounds: f32,
+        oob_force: f32,
+        max_speed: f32,
+        min_speed: f32,
+        map_fidelity: f32,
+        cell_impact: f32,
+        sense_distance: f32,
+        sense_force: f32,
+        fade_power: f32,
+        erase_power: f32,
+        instability_score: f32,
+        trail_ceiling: f32,
+        // Live particles, the lifecycle kernel rewrites this on the GPU every step
+        num_particles: u32,
+        respawn_chance: f32,
+        max_particles: u32,
+        energy_decay: f32,
+        energy_gain: f32,
+        reproduce_energy: f32,
+    }
+}
+
+impl SimulationConstants {
+    /// Bytes in the uniform buffer
+    pub const SIZE: usize = std::mem::size_of::<SimulationConstants>();
+
+    /// Where `num_particles` sits, the lifecycle kernel copies the new population there
+    pub const NUM_PARTICLES_OFFSET: usize = std::mem::offset_of!(SimulationConstants, num_particles);
+}
@@ -0,0 +1,188 @@
+use std::mem;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::simulation::{constants_bindings, map_bindings, particle_bindings};
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_layout, make_pipeline_layout, make_shader, reload_shader, shader, dispatch_grid, PipelineBindings};
+
+/// Steps without races, so the same seed, config and backend always give the
+/// same bits.
+///
+/// The map is diffused into a second buffer and copied back rather than
+/// changed in place, and particles count their deposits with atomics while the
+/// map stays read only, the counts are added to the map in a pass of their own.
+pub(crate) struct Deterministic {
+
+    // Written by `diffuse` and copied over the simulation's map
+    next_map_buffer: wgpu::Buffer,
+    // One atomic u32 per cell, emptied again by `apply_deposits`. Only held so
+    // the bind groups' buffer lives on
+    _deposit_buffer: wgpu::Buffer,
+
+    // Group 3 of the particle kernel, indicators and deposits
+    particle_bind_group: wgpu::BindGroup,
+    // Group 2 of the map kernels, the simulation's indicators
+    indicator_bind_group: wgpu::BindGroup,
+    // Group 3 of the map kernels, next map and deposits
+    map_bind_group: wgpu::BindGroup,
+
+    // Kept to rebuild the pipelines when the shaders change
+    pipeline_layout_particles: wgpu::PipelineLayout,
+    pipeline_layout_map: wgpu::PipelineLayout,
+
+    particles_pipeline: wgpu::ComputePipeline,
+    diffuse_pipeline: wgpu::ComputePipeline,
+    deposit_pipeline: wgpu::ComputePipeline,
+
+    map_size: wgpu::BufferAddress,
+    map_workgroups: (u32, u32),
+}
+
+fn make_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, entry_point: &str) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        module: shader,
+        entry_point,
+    })
+}
+
+/// Two map sized buffers, the indicators or next map next to the deposits
+fn cell_bindings(config: &Config) -> Vec<wgpu::BindingType> {
+    let _f = mem::size_of::<f32>() as u32;
+    let cells = config.map_fidelity * config.map_fidelity;
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: false }, &[ _f * cells, _f * cells ])
+}
+
+/// The race free particle and map kernels built in `Deterministic::new`
+pub(crate) fn pipeline_bindings(config: &Config) -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("compute.wgsl", &["main_deterministic"], vec![constants_bindings(), particle_bindings(config), map_bindings(config), cell_bindings(config)]),
+        PipelineBindings::new("compute_map.wgsl", &["diffuse", "apply_deposits"], vec![constants_bindings(), map_bindings(config), map_bindings(config), cell_bindings(config)]),
+    ]
+}
+
+/// `diffuse` and `apply_deposits` from compute_map.wgsl
+fn make_map_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+    (make_pipeline(device, layout, shader, "diffuse"), make_pipeline(device, layout, shader, "apply_deposits"))
+}
+
+impl Deterministic {
+
+    pub fn new(device: &wgpu::Device, config: &Config, binding_constants_compute: &wgpu::BindGroupLayout, binding_particles_compute: &wgpu::BindGroupLayout, binding_map_compute: &wgpu::BindGroupLayout, indicator_buffer: &wgpu::Buffer) -> Self {
+
+        let compute_particles_shader = make_shader(device, shader!("compute.wgsl"));
+        let compute_map_shader = make_shader(device, shader!("compute_map.wgsl"));
+
+        let cells = config.map_fidelity * config.map_fidelity;
+
+        // Setup Bind Layouts
+
+        let _f = mem::size_of::<f32>() as u32;
+
+        let binding_cells = make_layout(device, wgpu::ShaderStages::COMPUTE, &cell_bindings(config));
+
+        let pipeline_layout_particles = make_pipeline_layout(device, &[
+            binding_constants_compute,
+            binding_particles_compute,
+            binding_map_compute,
+            &binding_cells,
+        ]);
+
+        let pipeline_layout_map = make_pipeline_layout(device, &[
+            binding_constants_compute,
+            binding_map_compute,
+            binding_map_compute,
+            &binding_cells,
+        ]);
+
+        let particles_pipeline = make_pipeline(device, &pipeline_layout_particles, &compute_particles_shader, "main_deterministic");
+        let (diffuse_pipeline, deposit_pipeline) = make_map_pipelines(device, &pipeline_layout_map, &compute_map_shader);
+
+
+        // Buffers, zero is zero whether read as f32 or u32
+
+        let next_map_buffer = make_buffer(device, &vec![0.0f32; cells as usize]);
+        let deposit_buffer = make_buffer(device, &vec![0.0f32; cells as usize]);
+
+        let particle_bind_group = make_bind_group(device, &binding_cells, &[indicator_buffer, &deposit_buffer]);
+        let indicator_bind_group = make_bind_group(device, binding_map_compute, &[indicator_buffer]);
+        let map_bind_group = make_bind_group(device, &binding_cells, &[&next_map_buffer, &deposit_buffer]);
+
+        Deterministic {
+            next_map_buffer,
+            _deposit_buffer: deposit_buffer,
+            particle_bind_group,
+            indicator_bind_group,
+            map_bind_group,
+            pipeline_layout_particles,
+            pipeline_layout_map,
+            particles_pipeline,
+            diffuse_pipeline,
+            deposit_pipeline,
+            map_size: (_f * cells) as wgpu::BufferAddress,
+            map_workgroups: dispatch_grid(cells, device.limits().max_compute_workgroups_per_dimension),
+        }
+    }
+
+    /// Rebuilds the pipelines from `compute.wgsl` and `compute_map.wgsl` in `dir`, keeping the old ones on failure
+    pub fn reload_shaders(&mut self, device: &wgpu::Device, dir: &Path) -> Vec<String> {
+        let mut errors = Vec::new();
+        match reload_shader(device, dir, "compute.wgsl", |shader| make_pipeline(device, &self.pipeline_layout_particles, shader, "main_deterministic")) {
+            Ok(pipeline) => self.particles_pipeline = pipeline,
+            Err(e) => errors.push(e),
+        }
+        match reload_shader(device, dir, "compute_map.wgsl", |shader| make_map_pipelines(device, &self.pipeline_layout_map, shader)) {
+            Ok((diffuse_pipeline, deposit_pipeline)) => {
+                self.diffuse_pipeline = diffuse_pipeline;
+                self.deposit_pipeline = deposit_pipeline;
+            }
+            Err(e) => errors.push(e),
+        }
+        errors
+    }
+
+    /// Records a step in place of the simulation's own two passes
+    pub fn step(&self, command_encoder: &mut wgpu::CommandEncoder, const_bind_compute_group: &wgpu::BindGroup, particle_bind_group: &wgpu::BindGroup, map_bind_group: &wgpu::BindGroup, map_buffer: &wgpu::Buffer, particle_workgroups: (u32, u32)) {
+
+        command_encoder.push_debug_group("compute map changes");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.diffuse_pipeline);
+            cpass.set_bind_group(0, const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, map_bind_group, &[]);
+            cpass.set_bind_group(2, &self.indicator_bind_group, &[]);
+            cpass.set_bind_group(3, &self.map_bind_group, &[]);
+            cpass.dispatch(self.map_workgroups.0, self.map_workgroups.1, 1);
+        }
+        command_encoder.copy_buffer_to_buffer(&self.next_map_buffer, 0, map_buffer, 0, self.map_size);
+        command_encoder.pop_debug_group();
+
+        command_encoder.push_debug_group("compute boid movement");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.particles_pipeline);
+            cpass.set_bind_group(0, const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, particle_bind_group, &[]);
+            cpass.set_bind_group(2, map_bind_group, &[]);
+            cpass.set_bind_group(3, &self.particle_bind_group, &[]);
+            cpass.dispatch(particle_workgroups.0, particle_workgroups.1, 1);
+
+            cpass.set_pipeline(&self.deposit_pipeline);
+            cpass.set_bind_group(1, map_bind_group, &[]);
+            cpass.set_bind_group(2, &self.indicator_bind_group, &[]);
+            cpass.set_bind_group(3, &self.map_bind_group, &[]);
+            cpass.dispatch(self.map_workgroups.0, self.map_workgroups.1, 1);
+        }
+        command_encoder.pop_debug_group();
+    }
+}
+
+/// FNV-1a over the bytes of the map and then the live particles, printed at the
+/// end of a deterministic run so a result can be matched to the run behind it
+pub(crate) fn run_hash(map: &[f32], particles: &[f32]) -> u64 {
+    let bytes = bytemuck::cast_slice::<f32, u8>(map).iter().chain(bytemuck::cast_slice::<f32, u8>(particles));
+    bytes.fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,113 @@
+//! Writes the trail map and particles as NumPy arrays for the notebook.
+//!
+//! The map is saved as a `(MAP_FIDELITY, MAP_FIDELITY)` float32 array with
+//! row 0 at the top of the simulation (y = +1), so `plt.imshow` shows it the
+//! right way up. Particles are saved as an `(N, 7)` float32 array with the
+//! columns `[pos_x, pos_y, sense_distance, vel_x, vel_y, preference, energy]`,
+//! the fields of `Particle` in order.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::particle::Particle;
+
+/// Copies a buffer of f32 back from the GPU, blocking until it is available
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, len: usize) -> Vec<f32> {
+
+    // Zero sized copies are invalid, an empty population reads as nothing
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let size = (len * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).expect("Unable to read buffer back from the GPU");
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    staging.unmap();
+    data
+}
+
+/// Writes `data` as a little endian float32 `.npy` array of the given shape
+pub fn write_npy<W: Write>(mut writer: W, shape: &[usize], data: &[f32]) -> io::Result<()> {
+
+    assert_eq!(shape.iter().product::<usize>(), data.len(), "shape does not match the data");
+
+    let dims = shape.iter().map(|d| format!("{},", d)).collect::<String>();
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}", dims);
+
+    // Magic, version and header length take 10 bytes, the whole preamble is padded to 64
+    let total = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - total % 64) % 64));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in data {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Saves snapshots on demand or every `every` simulation steps
+pub struct Exporter {
+    dir: PathBuf,
+    every: u32,
+    npz: bool,
+}
+
+impl Exporter {
+
+    pub fn new(dir: PathBuf, every: u32, npz: bool) -> Self {
+        Exporter { dir, every, npz }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// True if a multiple of `every` was crossed going from `previous` to `step`
+    pub fn is_due(&self, previous: u32, step: u32) -> bool {
+        self.every > 0 && previous / self.every != step / self.every
+    }
+
+    pub fn export(&self, map: &[f32], map_size: usize, particles: &[f32], step: u32) -> io::Result<PathBuf> {
+
+        fs::create_dir_all(&self.dir)?;
+        let map_shape = [map_size, map_size];
+        let particle_shape = [particles.len() / Particle::FLOATS, Particle::FLOATS];
+
+        if self.npz {
+            // An npz is an uncompressed zip holding one .npy per array
+            let path = self.dir.join(format!("step_{:08}.npz", step));
+            let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(&path)?));
+            let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+            zip.start_file("map.npy", options)?;
+            write_npy(&mut zip, &map_shape, map)?;
+            zip.start_file("particles.npy", options)?;
+            write_npy(&mut zip, &particle_shape, particles)?;
+            zip.finish()?;
+            Ok(path)
+        } else {
+            write_npy(BufWriter::new(File::create(self.dir.join(format!("map_{:08}.npy", step)))?), &map_shape, map)?;
+            write_npy(BufWriter::new(File::create(self.dir.join(format!("particles_{:08}.npy", step)))?), &particle_shape, particles)?;
+            Ok(self.dir.clone())
+        }
+    }
+}
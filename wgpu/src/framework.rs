@@ -1,4 +1,6 @@
+use std::fmt;
 use std::future::Future;
+use particles::util::AdapterChoice;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::{Duration, Instant};
 use winit::{
@@ -8,9 +10,9 @@ use winit::{
 
 #[allow(dead_code)]
 pub fn cast_slice<T>(data: &[T]) -> &[u8] {
-    use std::{mem::size_of, slice::from_raw_parts};
+    use std::{mem::size_of_val, slice::from_raw_parts};
 
-    unsafe { from_raw_parts(data.as_ptr() as *const u8, data.len() * size_of::<T>()) }
+    unsafe { from_raw_parts(data.as_ptr() as *const u8, size_of_val(data)) }
 }
 
 #[allow(dead_code)]
@@ -59,18 +61,49 @@ pub trait Example: 'static + Sized {
     );
 }
 
+/// Why there is nothing to run the example on, from `setup` and `run_headless`
+#[derive(Debug)]
+pub enum SetupError {
+    Window(winit::error::OsError),
+    NoAdapter,
+    Adapter(String),
+    MissingFeatures { adapter: String, missing: wgpu::Features },
+    ShaderModel { adapter: String, required: wgpu::ShaderModel },
+    MissingDownlevel { adapter: String, missing: wgpu::DownlevelFlags },
+    RequestDevice { adapter: String, error: wgpu::RequestDeviceError },
+    NoSurfaceFormat { adapter: String },
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::Window(e) => write!(f, "Unable to open a window: {}", e),
+            SetupError::NoAdapter => write!(f, "No GPU adapters found on the system, not even a software one. Check the graphics drivers, or WGPU_BACKEND if it is set"),
+            SetupError::Adapter(e) => write!(f, "{}", e),
+            SetupError::MissingFeatures { adapter, missing } => write!(f, "{} does not support the features this needs: {:?}", adapter, missing),
+            SetupError::ShaderModel { adapter, required } => write!(f, "{} does not support the minimum shader model this needs: {:?}", adapter, required),
+            SetupError::MissingDownlevel { adapter, missing } => write!(f, "{} does not support the capabilities this needs: {:?}", adapter, missing),
+            SetupError::RequestDevice { adapter, error } => write!(f, "Unable to create a device on {}: {}", adapter, error),
+            SetupError::NoSurfaceFormat { adapter } => write!(f, "{} can't present to this window", adapter),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
+
 struct Setup {
     window: winit::window::Window,
     event_loop: EventLoop<()>,
     instance: wgpu::Instance,
     size: winit::dpi::PhysicalSize<u32>,
     surface: wgpu::Surface,
+    format: wgpu::TextureFormat,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
 }
 
-async fn setup<E: Example>(title: &str) -> Setup {
+async fn setup<E: Example>(title: &str, choice: &AdapterChoice) -> Result<Setup, SetupError> {
     #[cfg(not(target_arch = "wasm32"))]
     {
         env_logger::init();
@@ -84,7 +117,7 @@ async fn setup<E: Example>(title: &str) -> Setup {
         use winit::platform::windows::WindowBuilderExtWindows;
         builder = builder.with_no_redirection_bitmap(true);
     }
-    let window = builder.build(&event_loop).unwrap();
+    let window = builder.build(&event_loop).map_err(SetupError::Window)?;
 
     #[cfg(target_arch = "wasm32")]
     {
@@ -108,18 +141,13 @@ async fn setup<E: Example>(title: &str) -> Setup {
 
     log::info!("Initializing the surface...");
 
-    let backend = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
-
-    let instance = wgpu::Instance::new(backend);
+    let instance = wgpu::Instance::new(choice.backends());
     let (size, surface) = unsafe {
         let size = window.inner_size();
         let surface = instance.create_surface(&window);
         (size, surface)
     };
-    let adapter =
-        wgpu::util::initialize_adapter_from_env_or_default(&instance, backend, Some(&surface))
-            .await
-            .expect("No suitable GPU adapters found on the system!");
+    let adapter = request_adapter::<E>(&instance, choice, Some(&surface)).await?;
 
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -127,55 +155,105 @@ async fn setup<E: Example>(title: &str) -> Setup {
         println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
     }
 
-    let optional_features = E::optional_features();
-    let required_features = E::required_features();
-    let adapter_features = adapter.features();
-    assert!(
-        adapter_features.contains(required_features),
-        "Adapter does not support required features for this example: {:?}",
-        required_features - adapter_features
-    );
-
-    let required_downlevel_capabilities = E::required_downlevel_capabilities();
-    let downlevel_capabilities = adapter.get_downlevel_properties();
-    assert!(
-        downlevel_capabilities.shader_model >= required_downlevel_capabilities.shader_model,
-        "Adapter does not support the minimum shader model required to run this example: {:?}",
-        required_downlevel_capabilities.shader_model
-    );
-    assert!(
-        downlevel_capabilities
-            .flags
-            .contains(required_downlevel_capabilities.flags),
-        "Adapter does not support the downlevel capabilities required to run this example: {:?}",
-        required_downlevel_capabilities.flags - downlevel_capabilities.flags
-    );
-
-    // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the surface.
-    let needed_limits = E::required_limits().using_resolution(adapter.limits());
+    let format = surface.get_preferred_format(&adapter)
+        .ok_or_else(|| SetupError::NoSurfaceFormat { adapter: adapter.get_info().name })?;
 
-    let trace_dir = std::env::var("WGPU_TRACE");
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: (optional_features & adapter_features) | required_features,
-                limits: needed_limits,
-            },
-            trace_dir.ok().as_ref().map(std::path::Path::new),
-        )
-        .await
-        .expect("Unable to find a suitable GPU adapter!");
+    let (device, queue) = request_device::<E>(&adapter).await?;
 
-    Setup {
+    Ok(Setup {
         window,
         event_loop,
         instance,
         size,
         surface,
+        format,
         adapter,
         device,
         queue,
+    })
+}
+
+/// Whether the adapter has everything the example requires
+fn check_adapter<E: Example>(adapter: &wgpu::Adapter) -> Result<(), SetupError> {
+    let name = adapter.get_info().name;
+
+    let required_features = E::required_features();
+    let adapter_features = adapter.features();
+    if !adapter_features.contains(required_features) {
+        return Err(SetupError::MissingFeatures { adapter: name, missing: required_features - adapter_features });
+    }
+
+    let required_downlevel_capabilities = E::required_downlevel_capabilities();
+    let downlevel_capabilities = adapter.get_downlevel_properties();
+    if downlevel_capabilities.shader_model < required_downlevel_capabilities.shader_model {
+        return Err(SetupError::ShaderModel { adapter: name, required: required_downlevel_capabilities.shader_model });
+    }
+    if !downlevel_capabilities.flags.contains(required_downlevel_capabilities.flags) {
+        return Err(SetupError::MissingDownlevel { adapter: name, missing: required_downlevel_capabilities.flags - downlevel_capabilities.flags });
+    }
+
+    Ok(())
+}
+
+/// The adapter picked by `choice`, `WGPU_ADAPTER_NAME` or the default one. Only
+/// the default falls back to the software adapter if it is missing or lacks
+/// something the example needs, an adapter asked for is used or refused as it is
+async fn request_adapter<E: Example>(instance: &wgpu::Instance, choice: &AdapterChoice, surface: Option<&wgpu::Surface>) -> Result<wgpu::Adapter, SetupError> {
+    if choice.is_explicit() {
+        let adapter = choice.find(instance, surface).await.map_err(SetupError::Adapter)?;
+        check_adapter::<E>(&adapter)?;
+        return Ok(adapter);
+    }
+
+    let first = choice.find(instance, surface).await.ok();
+    let reason = match &first {
+        Some(adapter) => match check_adapter::<E>(adapter) {
+            Ok(()) => return Ok(first.unwrap()),
+            Err(e) => e,
+        },
+        None => SetupError::NoAdapter,
+    };
+
+    let fallback = AdapterChoice { fallback: true, ..choice.clone() }.find(instance, surface).await;
+    match fallback {
+        Ok(adapter) if check_adapter::<E>(&adapter).is_ok() => {
+            log::warn!("{}", reason);
+            #[cfg(not(target_arch = "wasm32"))]
+            println!("{}, falling back to the software adapter {}", reason, adapter.get_info().name);
+            Ok(adapter)
+        }
+        _ => Err(reason),
+    }
+}
+
+async fn request_device<E: Example>(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), SetupError> {
+    let optional_features = E::optional_features();
+    let required_features = E::required_features();
+    let adapter_features = adapter.features();
+
+    // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the surface.
+    // Buffers and dispatches may be as large as the adapter allows too, the example sizes itself to `device.limits()`
+    let needed_limits = particles::util::using_buffer_limits(E::required_limits().using_resolution(adapter.limits()), &adapter.limits());
+
+    let trace_dir = std::env::var("WGPU_TRACE");
+    let request = |limits| adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            features: (optional_features & adapter_features) | required_features,
+            limits,
+        },
+        trace_dir.as_ref().ok().map(std::path::Path::new),
+    );
+
+    // Adapters short of the requested limits still get a device with what they
+    // have, the example then has to size itself to `device.limits()`
+    match request(needed_limits).await {
+        Ok(device) => Ok(device),
+        Err(first) => {
+            log::warn!("Requested limits not available ({}), retrying with the adapter's own", first);
+            request(adapter.limits()).await
+                .map_err(|error| SetupError::RequestDevice { adapter: adapter.get_info().name, error })
+        }
     }
 }
 
@@ -186,6 +264,7 @@ fn start<E: Example>(
         instance,
         size,
         surface,
+        format,
         adapter,
         device,
         queue,
@@ -194,7 +273,7 @@ fn start<E: Example>(
     let spawner = Spawner::new();
     let mut config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: surface.get_preferred_format(&adapter).unwrap(),
+        format,
         width: size.width,
         height: size.height,
         present_mode: wgpu::PresentMode::Mailbox,
@@ -306,13 +385,26 @@ fn start<E: Example>(
                     }
                 }
 
+                // An outdated or lost surface is reconfigured and tried once more,
+                // a frame that still can't be had is skipped rather than panicking
                 let frame = match surface.get_current_texture() {
                     Ok(frame) => frame,
+                    Err(wgpu::SurfaceError::OutOfMemory) => {
+                        log::error!("Out of memory acquiring the next frame");
+                        #[cfg(not(target_arch = "wasm32"))]
+                        eprintln!("Out of memory acquiring the next frame, exiting");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
                     Err(_) => {
                         surface.configure(&device, &config);
-                        surface
-                            .get_current_texture()
-                            .expect("Failed to acquire next surface texture!")
+                        match surface.get_current_texture() {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                log::warn!("Skipping a frame: {}", e);
+                                return;
+                            }
+                        }
                     }
                 };
                 let view = frame
@@ -367,18 +459,108 @@ impl Spawner {
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn run<E: Example>(title: &str) {
-    let setup = pollster::block_on(setup::<E>(title));
+pub fn run<E: Example>(title: &str, choice: &AdapterChoice) -> Result<(), SetupError> {
+    let setup = pollster::block_on(setup::<E>(title, choice))?;
     start::<E>(setup);
+    Ok(())
+}
+
+/// Runs the example for a fixed number of frames without a window, rendering
+/// into an offscreen texture of the given size
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless<E: Example>(width: u32, height: u32, frames: u32, choice: &AdapterChoice) -> Result<(), SetupError> {
+    env_logger::init();
+
+    let instance = wgpu::Instance::new(choice.backends());
+    let adapter = pollster::block_on(request_adapter::<E>(&instance, choice, None))?;
+
+    let adapter_info = adapter.get_info();
+    println!("Using {} ({:?}) headless", adapter_info.name, adapter_info.backend);
+
+    let (device, queue) = pollster::block_on(request_device::<E>(&adapter))?;
+
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+    };
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage,
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let spawner = Spawner::new();
+    let mut example = E::init(&config, &adapter, &device, &queue);
+
+    let start = Instant::now();
+    for frame in 1..=frames {
+        example.render(&view, &device, &queue, &spawner);
+        device.poll(wgpu::Maintain::Wait);
+        spawner.run_until_stalled();
+
+        if frame % 100 == 0 {
+            println!(
+                "Frame {}/{}, avg frame time {}ms",
+                frame,
+                frames,
+                start.elapsed().as_secs_f32() * 1000.0 / frame as f32
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints every adapter on `backends` with its info, whether the example can
+/// run on it, its features, downlevel capabilities and limits
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_adapters<E: Example>(backends: wgpu::Backends) {
+    let instance = wgpu::Instance::new(backends);
+    let adapters: Vec<_> = instance.enumerate_adapters(backends).collect();
+    if adapters.is_empty() {
+        println!("No adapters found on {:?}", backends);
+    }
+
+    for (i, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        println!("{}: {} ({:?}, {:?}, vendor {:#06x}, device {:#06x})", i, info.name, info.backend, info.device_type, info.vendor, info.device);
+        match check_adapter::<E>(adapter) {
+            Ok(()) => println!("   usable"),
+            Err(e) => println!("   not usable: {}", e),
+        }
+        let downlevel = adapter.get_downlevel_properties();
+        println!("   features: {:?}", adapter.features());
+        println!("   shader model: {:?}", downlevel.shader_model);
+        println!("   downlevel flags: {:?}", downlevel.flags);
+        println!("   limits: {:#?}", adapter.limits());
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
-pub fn run<E: Example>(title: &str) {
+pub fn run<E: Example>(title: &str, choice: &AdapterChoice) {
     use wasm_bindgen::{prelude::*, JsCast};
 
     let title = title.to_owned();
+    let choice = choice.clone();
     wasm_bindgen_futures::spawn_local(async move {
-        let setup = setup::<E>(&title).await;
+        let setup = match setup::<E>(&title, &choice).await {
+            Ok(setup) => setup,
+            Err(e) => {
+                web_sys::console::error_1(&e.to_string().into());
+                return;
+            }
+        };
         let start_closure = Closure::once_into_js(move || start::<E>(setup));
 
         // make sure to handle JS exceptions thrown inside start.
@@ -0,0 +1,25 @@
+pub mod config;
+pub mod constants;
+mod deterministic;
+pub mod export;
+mod lifecycle;
+pub mod map_init;
+pub mod metrics;
+pub mod network;
+pub mod parity;
+pub mod particle;
+pub mod post;
+pub mod reference;
+mod render;
+pub mod simulation;
+pub mod spawn;
+pub mod sweep;
+pub mod util;
+
+#[cfg(feature = "python")]
+mod python;
+
+pub use config::{Config, MAX_MAP_FIDELITY};
+pub use constants::SimulationConstants;
+pub use particle::Particle;
+pub use simulation::Simulation;
@@ -0,0 +1,174 @@
+use std::mem;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::constants::SimulationConstants;
+use crate::particle::Particle;
+use crate::simulation::{constants_bindings, map_bindings};
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_compute_pipeline, make_layout, make_pipeline_layout, make_shader, reload_shader, shader, PipelineBindings};
+
+// Where `count` sits in the counter buffer, after the atomic `next`
+const COUNT_OFFSET: wgpu::BufferAddress = 4;
+
+/// Energy, death and reproduction.
+///
+/// Agents are compacted from one particle buffer into the other every step,
+/// so the simulation's own particle buffer is paired with a second one here
+/// and the two swap roles each step. The live count never leaves the GPU, it
+/// is copied straight into the constants the other kernels read.
+pub(crate) struct Lifecycle {
+
+    // The other half of the particle ping pong, bound like the simulation's own
+    raw_particle_buffer: wgpu::Buffer,
+    particle_bind_group: wgpu::BindGroup,
+
+    counter_buffer: wgpu::Buffer,
+
+    // Read from `current`, written to the other one
+    input_bind_groups: [wgpu::BindGroup; 2],
+    output_bind_groups: [wgpu::BindGroup; 2],
+
+    // Kept to rebuild the pipelines when the shader changes
+    pipeline_layout: wgpu::PipelineLayout,
+    lifecycle_pipeline: wgpu::ComputePipeline,
+    finish_pipeline: wgpu::ComputePipeline,
+
+    // Which particle buffer holds the live agents, 0 is the simulation's
+    current: usize,
+}
+
+/// The live particles, read only
+fn input_bindings(config: &Config) -> Vec<wgpu::BindingType> {
+    let _particles = (Particle::SIZE as u32) * config.max_particles;
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: true }, &[ _particles ])
+}
+
+/// The particles of the next step and the counter handing out their slots
+fn output_bindings(config: &Config) -> Vec<wgpu::BindingType> {
+    let _f = mem::size_of::<f32>() as u32;
+    let _particles = (Particle::SIZE as u32) * config.max_particles;
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: false }, &[ _particles, 2 * _f ])
+}
+
+/// Both kernels built in `Lifecycle::new`, sharing one layout
+pub(crate) fn pipeline_bindings(config: &Config) -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("compute_lifecycle.wgsl", &["main", "finish"], vec![constants_bindings(), input_bindings(config), output_bindings(config), map_bindings(config)]),
+    ]
+}
+
+/// The feeding and compaction kernel, and the one publishing the new count
+fn make_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+    let lifecycle_pipeline = make_compute_pipeline(device, layout, shader);
+    let finish_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        module: shader,
+        entry_point: "finish",
+    });
+    (lifecycle_pipeline, finish_pipeline)
+}
+
+impl Lifecycle {
+
+    pub fn new(device: &wgpu::Device, config: &Config, binding_constants_compute: &wgpu::BindGroupLayout, binding_particles_compute: &wgpu::BindGroupLayout, binding_map_compute: &wgpu::BindGroupLayout, particle_buffer: &wgpu::Buffer) -> Self {
+
+        let shader = make_shader(device, shader!("compute_lifecycle.wgsl"));
+
+        // Setup Bind Layouts
+
+        let binding_input = make_layout(device, wgpu::ShaderStages::COMPUTE, &input_bindings(config));
+        let binding_output = make_layout(device, wgpu::ShaderStages::COMPUTE, &output_bindings(config));
+
+        let pipeline_layout = make_pipeline_layout(device, &[
+            binding_constants_compute,
+            &binding_input,
+            &binding_output,
+            binding_map_compute,
+        ]);
+
+        let (lifecycle_pipeline, finish_pipeline) = make_pipelines(device, &pipeline_layout, &shader);
+
+
+        // Buffers, the second particle buffer starts empty
+
+        let raw_particle_buffer = make_buffer(device, &vec![0.0f32; config.max_particles as usize * Particle::FLOATS]);
+        let particle_bind_group = make_bind_group(device, binding_particles_compute, &[&raw_particle_buffer]);
+
+        let counter_buffer = make_buffer(device, &[0.0f32; 2]);
+
+        let particle_buffers = [particle_buffer, &raw_particle_buffer];
+        let input_bind_groups = [0, 1].map(|i| make_bind_group(device, &binding_input, &[particle_buffers[i]]));
+        let output_bind_groups = [0, 1].map(|i| make_bind_group(device, &binding_output, &[particle_buffers[i], &counter_buffer]));
+
+        Lifecycle {
+            raw_particle_buffer,
+            particle_bind_group,
+            counter_buffer,
+            input_bind_groups,
+            output_bind_groups,
+            pipeline_layout,
+            lifecycle_pipeline,
+            finish_pipeline,
+            current: 0,
+        }
+    }
+
+    /// Rebuilds the pipelines from `compute_lifecycle.wgsl` in `dir`, keeping the old ones on failure
+    pub fn reload_shaders(&mut self, device: &wgpu::Device, dir: &Path) -> Result<(), String> {
+        let (lifecycle_pipeline, finish_pipeline) = reload_shader(device, dir, "compute_lifecycle.wgsl", |shader| make_pipelines(device, &self.pipeline_layout, shader))?;
+        self.lifecycle_pipeline = lifecycle_pipeline;
+        self.finish_pipeline = finish_pipeline;
+        Ok(())
+    }
+
+    /// Index of the particle buffer holding the live agents, 0 for the simulation's own
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The second particle buffer
+    pub fn particle_buffer(&self) -> &wgpu::Buffer {
+        &self.raw_particle_buffer
+    }
+
+    /// Bind group of the second particle buffer for the particle kernel
+    pub fn particle_bind_group(&self) -> &wgpu::BindGroup {
+        &self.particle_bind_group
+    }
+
+    /// Keeps the GPU's count in step when particles are added from the CPU
+    pub fn write_count(&self, queue: &wgpu::Queue, count: u32) {
+        queue.write_buffer(&self.counter_buffer, COUNT_OFFSET, bytemuck::bytes_of(&count));
+    }
+
+    /// Reads back the population the last step left behind
+    pub fn read_count(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        crate::export::read_buffer(device, queue, &self.counter_buffer, 2)[1].to_bits()
+    }
+
+    /// Records the feeding and compaction pass, then swaps the buffers
+    pub fn step(&mut self, command_encoder: &mut wgpu::CommandEncoder, const_bind_compute_group: &wgpu::BindGroup, map_bind_group: &wgpu::BindGroup, constant_data_buffer: &wgpu::Buffer, workgroups: (u32, u32)) {
+
+        let next = 1 - self.current;
+
+        command_encoder.push_debug_group("compute lifecycle");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.lifecycle_pipeline);
+            cpass.set_bind_group(0, const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, &self.input_bind_groups[self.current], &[]);
+            cpass.set_bind_group(2, &self.output_bind_groups[next], &[]);
+            cpass.set_bind_group(3, map_bind_group, &[]);
+            cpass.dispatch(workgroups.0, workgroups.1, 1);
+
+            cpass.set_pipeline(&self.finish_pipeline);
+            cpass.dispatch(1, 1, 1);
+        }
+        command_encoder.copy_buffer_to_buffer(&self.counter_buffer, COUNT_OFFSET, constant_data_buffer, SimulationConstants::NUM_PARTICLES_OFFSET as wgpu::BufferAddress, 4);
+        command_encoder.pop_debug_group();
+
+        self.current = next;
+    }
+}
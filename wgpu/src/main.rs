@@ -1,197 +1,111 @@
-use rand::{ distributions::{Distribution, Uniform}, SeedableRng, Rng};
-use std::{borrow::Cow, mem};
-use wgpu::util::DeviceExt;
-use std::convert::TryFrom;
+use particles::{export, metrics, network, post, Config, Simulation};
 
 #[path = "./framework.rs"]
 mod framework;
+mod camera;
+mod cli;
+mod recorder;
+mod watcher;
 
 // -------------------------------------------------------------------------------------------------
 // Handling control of the application
 // -------------------------------------------------------------------------------------------------
 
 
-// Particle Count
-const NUM_PARTICLES: u32 = 3000000;
-const WORKGROUPS: u32 = 1 + NUM_PARTICLES / 64;
+// Simulation parameters live in `Config`, see config.rs for the defaults
 
-// How many regions n x n we want to split the screen into
-const MAP_FIDELITY: f32 = 500.0;
-const MAP_WORKGROUPS: u32 = (1.0 + ((MAP_FIDELITY * MAP_FIDELITY) / 64.0)) as u32;
- 
-// Used to calculate step sizes
-const SIMULATION_SPEED: f32 = 1.0;
+// How many simulation steps run per frame
 const SIMULATION_ITTERATIONS: u32 = 1;
 
-// Where "OOB" starts and how hard to push them back in
-// DEAD
-const MAP_BOUNDERY: f32 = 0.85;
-// DEAD
-const OOB_FORCE: f32 = 0.003;
+// Particles spawned by a right click, and how much + and - grow or shrink the population
+const BURST_SIZE: u32 = 20000;
+const POPULATION_STEP: f32 = 1.25;
 
-// Max speed moved in 1.0 time step
-const MAX_SPEED: f32 = 0.01;
-const MIN_SPEED: f32 = 0.0002;
+// Room for particles spawned at runtime on top of the initial population
+const MAX_PARTICLES: u32 = 4000000;
 
-// How much the map changes
-const CELL_IMPACT: f32 = 0.001;
-
-// How powerful is the senses
-// DEAD
-const SENSE_DISTANCE: f32 = 0.04;
-const SENSE_FORCE: f32 = 0.4;
-
-// How powerful the fade is
-const FADE_POWER: f32 = 0.009;
-const ERASE_POWER: f32 = 0.99;
-
-// How stable it should be 1 is perfect
-// DEAD
-const INSTABLITY: f32 = 0.0;
+// Where trails saturate with --hdr, only values up to 1.0 can be shown without it
+const HDR_TRAIL_CEILING: f32 = 4.0;
 
 // Define a single state the represents the application
 struct State {
 
-    const_bind_compute_group: wgpu::BindGroup,
-    const_bind_vertex_group: wgpu::BindGroup,
+    simulation: Simulation,
 
-    raw_particle_buffer: wgpu::Buffer,
-    particle_bind_group: wgpu::BindGroup,
+    camera: camera::Camera,
 
-    raw_indicator_buffer: wgpu::Buffer,
-    indicator_bind_group_compute: wgpu::BindGroup,
+    post: Option<post::PostProcess>,
+    recorder: Option<recorder::Recorder>,
 
-    raw_map_buffer : wgpu::Buffer,
-    map_bind_group: wgpu::BindGroup,
+    exporter: export::Exporter,
+    export_requested: bool,
 
-    triangle_vertex_buffer: wgpu::Buffer,
-    square_vertex_buffer: wgpu::Buffer,
+    // Statistics of every `metrics_every`th step, with --metrics
+    metrics: Option<metrics::MetricsLog>,
+    metrics_every: u32,
 
-    compute_map_pipeline: wgpu::ComputePipeline,
-    compute_pipeline: wgpu::ComputePipeline,
-    pipeline_render_particles: wgpu::RenderPipeline,
-    pipeline_render_map: wgpu::RenderPipeline,
-    pipeline_render_indicators: wgpu::RenderPipeline,
+    // Graph of the trail network saved on G, and the food sources it is scored on
+    network_requested: bool,
+    network_threshold: f32,
+    food: Vec<[f32; 2]>,
 
-    frame_num: u32,
+    // Frame after which the run hash is printed, headless --deterministic runs only
+    hash_frame: Option<u32>,
 
-}
+    // Recompiles the pipelines when a shader is saved, with --watch-shaders
+    watcher: Option<watcher::ShaderWatcher>,
 
-// -------------------------------------------------------------------------------------------------
-// Utility functions
-// -------------------------------------------------------------------------------------------------
+    // Input waiting for the queue, handled at the start of the next frame
+    spawn_requested: bool,
+    population_change: Option<f32>,
 
-fn make_buffer ( device : &wgpu::Device, source : &Vec<f32> ) -> wgpu::Buffer {
-    return device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: None,
-        contents: bytemuck::cast_slice(source),
-        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::UNIFORM,
-    });
-}
+    frame_num: u32,
 
-fn make_shader ( device : &wgpu::Device, source : &str ) -> wgpu::ShaderModule {
-    return device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source))
-    });
 }
 
-fn make_binding_layout (device : &wgpu::Device, stage : wgpu::ShaderStages, bind_type : wgpu::BufferBindingType, sizes : &[u32]) -> wgpu::BindGroupLayout {
-
-    // Construct the entieries of the bind group
-    let mut enties = Vec::<wgpu::BindGroupLayoutEntry>::new();
-    for i in 0..sizes.len() {
-        enties.push(wgpu::BindGroupLayoutEntry {
-            count: None,
-            binding: i as u32,
-            visibility: stage,
-            ty: wgpu::BindingType::Buffer {
-                ty: bind_type,
-                has_dynamic_offset: false,
-                min_binding_size: wgpu::BufferSize::new(sizes[i] as _ ),
-            }
-        });
-    }
-
-    // Create the layout
-    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: enties.as_slice(),
-        label: None,
-    });
-
-    return layout;
-
+/// Prints why the app can't start and exits, for setup failures past the framework's
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
-fn make_pipeline_layout (device : &wgpu::Device, layouts : &[&wgpu::BindGroupLayout]) -> wgpu::PipelineLayout {
-
-    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: layouts,
-        push_constant_ranges: &[],
-    });
-
-    return layout;
+/// The settings above with the start given on the command line
+fn base_config(options: &cli::Options) -> Config {
+    Config {
+        trail_ceiling: if options.hdr { HDR_TRAIL_CEILING } else { Config::default().trail_ceiling },
+        spawn: options.spawn.clone(),
+        map_init: options.map_init.clone(),
+        seed: options.seed,
+        max_particles: MAX_PARTICLES,
+        lifecycle: options.lifecycle,
+        deterministic: options.deterministic,
+        ..Config::default()
+    }
 }
 
-fn make_compute_pipeline (device : &wgpu::Device, layout : &wgpu::PipelineLayout, shader : &wgpu::ShaderModule) -> wgpu::ComputePipeline {
-
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&layout),
-        module: &shader,
-        entry_point: "main",
-    });
+// -------------------------------------------------------------------------------------------------
+// Implement the window and state management
+// -------------------------------------------------------------------------------------------------
 
-    return pipeline;
-}
+impl State {
 
-fn make_render_pipeline (device : &wgpu::Device, config: &wgpu::SurfaceConfiguration, layout : &wgpu::PipelineLayout, shader : &wgpu::ShaderModule, buffers : &[wgpu::VertexBufferLayout]) -> wgpu::RenderPipeline {
-
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: shader,
-            entry_point: "main_vs",
-            buffers: buffers
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "main_fs",
-            targets: &[config.format.into()],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
-    });
-
-    return pipeline;
-}
+    /// records the map (and post processing) into view, without advancing the simulation
+    fn draw(&mut self, command_encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, view: &wgpu::TextureView) {
 
-fn make_bind_group (device : &wgpu::Device, layout : &wgpu::BindGroupLayout, buffers : &[&wgpu::Buffer]) -> wgpu::BindGroup {
+        // the scene goes to the HDR target when post processing is on
+        let scene_view = match &self.post {
+            Some(post) => post.hdr_view(),
+            None => view,
+        };
+        self.simulation.render(scene_view, command_encoder);
 
-    let mut entries = Vec::<wgpu::BindGroupEntry>::new();
-    for i in 0..buffers.len() {
-        entries.push(wgpu::BindGroupEntry {
-            binding: i as u32,
-            resource: buffers[i].as_entire_binding()
-        });
+        // bloom and tone map the HDR scene onto the screen
+        if let Some(post) = self.post.as_mut() {
+            post.run(command_encoder, queue, view);
+        }
     }
-
-    return device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: layout,
-        entries: &entries,
-        label: None,
-    });
-
 }
 
-// -------------------------------------------------------------------------------------------------
-// Implement the window and state management
-// -------------------------------------------------------------------------------------------------
-
 impl framework::Example for State {
     fn required_limits() -> wgpu::Limits {
         wgpu::Limits::downlevel_defaults()
@@ -205,237 +119,162 @@ impl framework::Example for State {
     }
 
     /// constructs initial instance of State struct
-    fn init( config: &wgpu::SurfaceConfiguration, _adapter: &wgpu::Adapter, device: &wgpu::Device, _queue: &wgpu::Queue) -> Self {
-        
-
-        // Create the shaders
-        let compute_particles_shader = make_shader(device, include_str!("compute.wgsl"));
-        let compute_map_shader = make_shader(device, include_str!("compute_map.wgsl"));
-        let render_particle_shader = make_shader(device, include_str!("draw.wgsl"));
-        let render_map_shader = make_shader(device, include_str!("render_map.wgsl"));
-        let render_indicator_shader = make_shader(device, include_str!("render_indicators.wgsl"));
-
-
-        // Construct constants to be bound to shaders
-        let constant_data = [
-            SIMULATION_SPEED,
-            MAP_BOUNDERY,
-            OOB_FORCE,
-            MAX_SPEED,
-            MIN_SPEED,
-            MAP_FIDELITY,
-            CELL_IMPACT,
-            SENSE_DISTANCE,
-            SENSE_FORCE,
-            FADE_POWER,
-            ERASE_POWER,
-            INSTABLITY
-        ];
-        let constant_data_buffer = make_buffer(device, &constant_data.to_vec());
-
-        let triangle_vertex_data = [
-            0.0f32  ,   0.01    ,   0.0,
-            0.005   ,   -0.005  ,   0.0,
-            -0.005  ,   -0.005  ,   0.0];
-        let triangle_vertex_buffer = make_buffer(device, &triangle_vertex_data.to_vec());
-
-        let square_vertex_data = [
-            -0.5f32 ,   0.5     ,   0.0,
-            0.5     ,   0.5     ,   0.0,
-            0.5     ,   -0.5    ,   0.0,
-            -0.5    ,   0.5     ,   0.0,
-            0.5     ,   -0.5    ,   0.0,
-            -0.5    ,   -0.5    ,   0.0];
-        let square_vertex_buffer = make_buffer(device, &square_vertex_data.to_vec());
-
-        // Setup Bind Layouts
-
-        let _f = mem::size_of::<f32>() as u32;
-
-        let _size = (_f as u32) * (constant_data.len() as u32);
-        let _bind_type = wgpu::BufferBindingType::Uniform;
-        let binding_constants_compute = make_binding_layout(device, wgpu::ShaderStages::COMPUTE, _bind_type, &[ _size ]);
-        let binding_constants_vertex = make_binding_layout(device, wgpu::ShaderStages::VERTEX, _bind_type, &[ _size ]);
-
-        let _size = _f * 6 * usize::try_from(NUM_PARTICLES).unwrap() as u32;
-        let _bind_type = wgpu::BufferBindingType::Storage { read_only: false };
-        let binding_particles_compute = make_binding_layout(device, wgpu::ShaderStages::COMPUTE, _bind_type, &[ _size ]);
-
-        let _size = _f * ((MAP_FIDELITY * MAP_FIDELITY) as u32);
-        let _bind_type = wgpu::BufferBindingType::Storage { read_only: false };
-        let _bind_type_2 = wgpu::BufferBindingType::Uniform;
-        let binding_map_compute = make_binding_layout(device, wgpu::ShaderStages::COMPUTE, _bind_type, &[ _size ]);
-        let binding_map_vertex = make_binding_layout(device, wgpu::ShaderStages::VERTEX, _bind_type_2, &[ _size ]);
-
-
-        // Create the pipeline layouts
-
-        let pipeline_layout_compute = make_pipeline_layout(device, &[ 
-            &binding_constants_compute, 
-            &binding_particles_compute, 
-            &binding_map_compute,
-            &binding_map_compute,
-        ]);
-
-        let pipeline_layout_compute_map = make_pipeline_layout(device, &[ 
-            &binding_constants_compute, 
-            &binding_map_compute,
-            &binding_map_compute
-        ]);
-
-        let pipeline_layout_render_map = make_pipeline_layout(device, &[
-            &binding_constants_vertex
-        ]);
-
-        let pipeline_layout_render_indicators = make_pipeline_layout(device, &[
-            &binding_constants_vertex
-        ]);
-
-        let pipeline_layout_render_particles = make_pipeline_layout(device, &[
-            &binding_constants_vertex
-        ]);
-
-
-        // create pipelines with empty bind group layout
-
-        let compute_pipeline = make_compute_pipeline(device, &pipeline_layout_compute, &compute_particles_shader);
-        let compute_map_pipeline = make_compute_pipeline(device, &pipeline_layout_compute_map, &compute_map_shader);
-
-        let pipeline_render_particles = make_render_pipeline(device, config, &pipeline_layout_render_particles, &render_particle_shader, &[
-            wgpu::VertexBufferLayout {
-                array_stride: 6 * 4,
-                step_mode: wgpu::VertexStepMode::Instance,
-                attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
-            },
-            wgpu::VertexBufferLayout {
-                array_stride: 3 * 4,
-                step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![2 => Float32x3],
-            },
-        ]);
-
-
-        let pipeline_render_map = make_render_pipeline(device, config, &pipeline_layout_render_map, &render_map_shader, &[
-            wgpu::VertexBufferLayout {
-                array_stride: 1 * 4,
-                step_mode: wgpu::VertexStepMode::Instance,
-                attributes: &wgpu::vertex_attr_array![0 => Float32],
-            },
-            wgpu::VertexBufferLayout {
-                array_stride: 3 * 4,
-                step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![1 => Float32x3],
-            },
-        ]);
-
-        
-        let pipeline_render_indicators = make_render_pipeline(device, config, &pipeline_layout_render_indicators, &render_indicator_shader, &[
-            wgpu::VertexBufferLayout {
-                array_stride: 1 * 4,
-                step_mode: wgpu::VertexStepMode::Instance,
-                attributes: &wgpu::vertex_attr_array![0 => Float32],
-            },
-            wgpu::VertexBufferLayout {
-                array_stride: 3 * 4,
-                step_mode: wgpu::VertexStepMode::Vertex,
-                attributes: &wgpu::vertex_attr_array![1 => Float32x3],
-            },
-        ]);
-
-        // Actually create the buffers and bindings data
-
-
-        // Particles
-
-        let mut raw_particle_data = vec![ 0.0f32; (NUM_PARTICLES * 6) as usize];
-        let mut rng = rand::rngs::StdRng::seed_from_u64(43);
-        let unif = Uniform::new_inclusive(-1.0f32, 1.0);
-        for chunk in raw_particle_data.chunks_mut(6) {
-            while true {
-                let mut x = rng.sample(&unif);
-                let mut y = rng.sample(&unif);
-                if x * x + y * y >= 1.0f32 { }
-                else {
-                    x = x * 0.5f32;
-                    y = y * 0.5f32;
-                    chunk[0] =  x; // posx
-                    chunk[1] =  y; // posy
-                    chunk[2] =  0.04; // look distance            
-                    chunk[3] =  -x * 0.01 + rng.sample(&unif) * 0.01; // velx
-                    chunk[4] =  -y * 0.01 + rng.sample(&unif) * 0.01; // vely
-
-                    let mut v = (rng.sample(&unif)+1.0)/2.0;
-                    if v < 0.5 { v = 0.0;  }
-                    else { v = 1.0; }
-                    
-                    chunk[5] =  v; // density preference
-                    //chunk[5] = chunk[5] * chunk[5];
-                    break;
-                }
-            }
-        }
+    fn init( config: &wgpu::SurfaceConfiguration, adapter: &wgpu::Adapter, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+
+
+        let options = cli::options();
+
+        // The scene is drawn straight to the screen, or into the HDR target for post processing
+        let target_format = if options.hdr { post::HDR_FORMAT } else { config.format };
 
-        let raw_particle_buffer = make_buffer(device, &raw_particle_data);
-        let particle_bind_group = make_bind_group(device, &binding_particles_compute, &[&raw_particle_buffer]);
-        
+        // Particles, map, the kernels moving them and the pipelines drawing them
+        let simulation_config = Config {
+            target_format,
+            ..base_config(options)
+        };
+        let simulation = Simulation::new(device, queue, simulation_config).unwrap_or_else(|e| exit_with(&e));
 
-        // Map Data
+        // A device that can't bind the whole population or map at once gets as much as fits
+        for change in simulation.fitted() {
+            println!("{} {}", adapter.get_info().name, change);
+        }
 
-        let mut raw_map_data = vec![0.0f32; ((MAP_FIDELITY * MAP_FIDELITY)) as usize];
-        let raw_map_buffer = make_buffer(device, &raw_map_data);
-        let map_bind_group = make_bind_group(device, &binding_map_compute, &[&raw_map_buffer]);
+        // Pan and zoom over the rendered output
+        let camera = camera::Camera::new(config.width, config.height);
 
-        let mut raw_indicator_map_data = vec![0.0f32; ((MAP_FIDELITY * MAP_FIDELITY)) as usize];
-        let raw_indicator_buffer = make_buffer(device, &raw_indicator_map_data);
-        let indicator_bind_group_compute = make_bind_group(device, &binding_map_compute, &[&raw_indicator_buffer]);
+        // T cycles the tone mapping at runtime
+        let post = if options.hdr {
+            Some(post::PostProcess::new(device, config.format, config.width, config.height, options.post))
+        } else {
+            None
+        };
 
+        // Recorded frames are drawn into their own target and copied out every frame
+        let recorder = options.record.as_ref().map(|record| {
+            recorder::Recorder::new(device, config.format, options.width, options.height, record)
+                .unwrap_or_else(|e| exit_with(&format!("Unable to start recording: {}", e)))
+        });
 
-        // Constants Data
+        // NumPy snapshots of the map and particles, on demand or periodically
+        let exporter = export::Exporter::new(options.export_dir.clone(), options.export_every, options.npz);
 
-        let const_bind_compute_group = make_bind_group(device, &binding_constants_compute, &[&constant_data_buffer]);
-        let const_bind_vertex_group = make_bind_group(device, &binding_constants_vertex, &[&constant_data_buffer]);
+        // Time series of trail and particle statistics
+        let metrics = options.metrics.as_ref().map(|path| {
+            metrics::MetricsLog::create(path)
+                .unwrap_or_else(|e| exit_with(&format!("Unable to create {}: {}", path.display(), e)))
+        });
+
+        // Shaders edited on disk replace the built in ones while running
+        let watcher = options.watch_shaders.clone().map(watcher::ShaderWatcher::new);
 
 
         State {
 
-            const_bind_compute_group,
-            const_bind_vertex_group,
+            simulation,
+
+            camera,
 
-            raw_particle_buffer,
-            particle_bind_group,
+            post,
+            recorder,
 
-            raw_indicator_buffer,
-            indicator_bind_group_compute,
+            exporter,
+            export_requested: false,
 
-            raw_map_buffer,
-            map_bind_group,
-            
-            triangle_vertex_buffer,
-            square_vertex_buffer,
+            metrics,
+            metrics_every: options.metrics_every,
 
-            compute_map_pipeline,
-            compute_pipeline,
-            pipeline_render_particles,
-            pipeline_render_map,
-            pipeline_render_indicators,
+            network_requested: false,
+            network_threshold: options.network_threshold,
+            food: options.food.clone(),
+
+            hash_frame: (options.headless && options.deterministic).then_some(options.frames),
+
+            watcher,
+
+            spawn_requested: false,
+            population_change: None,
 
             frame_num : 0
         }
     }
 
     /// update is called for any WindowEvent not handled by the framework
-    fn update(&mut self, _event: winit::event::WindowEvent) {
-        //empty
+    fn update(&mut self, event: winit::event::WindowEvent) {
+        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+        // Mouse wheel zooms, left drag pans and C recenters the view. Right click
+        // spawns a burst under the cursor, + and - grow and shrink the population
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: winit::event::MouseButton::Right,
+                ..
+            } => self.spawn_requested = true,
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(key @ (VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd | VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract)),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                let grow = !matches!(key, VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract);
+                self.population_change = Some(if grow { POPULATION_STEP } else { 1.0 / POPULATION_STEP });
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::C),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => self.camera.reset(),
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::E),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => self.export_requested = true,
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::G),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => self.network_requested = true,
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::T),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => {
+                if let Some(post) = self.post.as_mut() {
+                    let mut settings = post.settings();
+                    settings.tone_mapping = settings.tone_mapping.next();
+                    println!("Tone mapping {:?}", settings.tone_mapping);
+                    post.set_settings(settings);
+                }
+            }
+            _ => self.camera.handle_event(&event),
+        }
     }
 
     /// resize is called on WindowEvent::Resized events
     fn resize(
         &mut self,
-        _sc_desc: &wgpu::SurfaceConfiguration,
-        _device: &wgpu::Device,
+        sc_desc: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
         _queue: &wgpu::Queue,
     ) {
-        //empty
+        self.camera.resize(sc_desc.width, sc_desc.height);
+        if let Some(post) = self.post.as_mut() {
+            post.resize(device, sc_desc.width, sc_desc.height);
+        }
     }
 
     /// render is called each frame, dispatching compute groups proportional
@@ -448,99 +287,185 @@ impl framework::Example for State {
         _spawner: &framework::Spawner,
     ) {
 
-        // create render pass descriptor and its color attachments
-        let color_attachments = [wgpu::RenderPassColorAttachment {
-            view,
-            resolve_target: None,
-            ops: wgpu::Operations {
-                load: wgpu::LoadOp::Load,
-                store: true,
-            },
-        }];
-        let render_pass_descriptor = wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &color_attachments,
-            depth_stencil_attachment: None,
-        };
+        // recompile the pipelines if a shader was saved, a broken one keeps running the old version
+        if let Some(watcher) = self.watcher.as_mut() {
+            if watcher.changed() {
+                match self.simulation.reload_shaders(device, watcher.dir()) {
+                    Ok(()) => println!("Reloaded shaders from {}", watcher.dir().display()),
+                    Err(e) => eprintln!("Shader reload failed, keeping the previous pipelines:\n{}", e),
+                }
+            }
+        }
+
+        // upload the view transform if it changed since the last frame
+        if self.camera.take_dirty() {
+            self.simulation.set_view(queue, self.camera.offset(), self.camera.scale());
+        }
+
+        // the map is drawn with y flipped against particle positions, undo that for the cursor
+        if self.spawn_requested {
+            self.spawn_requested = false;
+            let [x, y] = self.camera.cursor_position();
+            self.simulation.spawn_at(device, queue, x, -y, BURST_SIZE);
+            println!("{} particles", self.simulation.config().num_particles);
+        }
+
+        if let Some(factor) = self.population_change.take() {
+            self.simulation.sync_particle_count(device, queue);
+            let mut config = self.simulation.config().clone();
+            // growing always adds at least one, or small populations would be stuck
+            let scaled = (config.num_particles as f32 * factor) as u32;
+            let scaled = if factor > 1.0 { scaled.max(config.num_particles + 1) } else { scaled };
+            config.num_particles = scaled.clamp(1, config.max_particles);
+            if self.simulation.set_config(device, queue, config).is_ok() {
+                println!("{} particles", self.simulation.config().num_particles);
+            }
+        }
 
         // get command encoder
         let mut command_encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         for _ in 0..SIMULATION_ITTERATIONS {
-            command_encoder.push_debug_group("compute map changes");
-            {
-                // compute pass
-                let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-                cpass.set_pipeline(&self.compute_map_pipeline);
-                cpass.set_bind_group(0, &self.const_bind_compute_group, &[]);
-                cpass.set_bind_group(1, &self.map_bind_group, &[]);
-                cpass.set_bind_group(2, &self.indicator_bind_group_compute, &[]);
-                cpass.dispatch(MAP_WORKGROUPS, 1, 1);
-            }
-            command_encoder.pop_debug_group();
-
-            command_encoder.push_debug_group("compute boid movement");
-            {
-                // compute pass
-                let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-                cpass.set_pipeline(&self.compute_pipeline);
-                cpass.set_bind_group(0, &self.const_bind_compute_group, &[]);
-                cpass.set_bind_group(1, &self.particle_bind_group, &[]);
-                cpass.set_bind_group(2, &self.map_bind_group, &[]);
-                cpass.set_bind_group(3, &self.indicator_bind_group_compute, &[]);
-                cpass.dispatch(WORKGROUPS, 1, 1);
-            }
-            command_encoder.pop_debug_group();
-
-            
+            self.simulation.step(&mut command_encoder);
         }
 
-        command_encoder.push_debug_group("render map");
-        {
-            // render pass map
-            let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
-            rpass.set_pipeline(&self.pipeline_render_map);
-            rpass.set_bind_group(0, &self.const_bind_vertex_group, &[]);
-            rpass.set_vertex_buffer(0, self.raw_map_buffer.slice(..));
-            rpass.set_vertex_buffer(1, self.square_vertex_buffer.slice(..));
-            rpass.draw(0..6, 0..((MAP_FIDELITY * MAP_FIDELITY) as u32));
-        }
+        // while recording the frame is drawn into the recording target, copied out and shown from there
+        let mut recorder = self.recorder.take();
+        command_encoder.push_debug_group("render");
+        self.draw(&mut command_encoder, queue, recorder.as_ref().map_or(view, |recorder| recorder.view()));
         command_encoder.pop_debug_group();
 
-        // command_encoder.push_debug_group("render boids");
-        // {
-        //     // render pass boids
-        //     let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
-        //     rpass.set_pipeline(&self.pipeline_render_particles);
-        //     rpass.set_bind_group(0, &self.const_bind_vertex_group, &[]);
-        //     rpass.set_vertex_buffer(0, self.raw_particle_buffer.slice(..));
-        //     rpass.set_vertex_buffer(1, self.triangle_vertex_buffer.slice(..));
-        //     rpass.draw(0..3, 0..NUM_PARTICLES);
-        // }
-        // command_encoder.pop_debug_group();
-
-        // command_encoder.push_debug_group("render indicators");
-        // {
-        //     // render pass indicators
-        //     let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
-        //     rpass.set_pipeline(&self.pipeline_render_indicators);
-        //     rpass.set_bind_group(0, &self.const_bind_vertex_group, &[]);
-        //     rpass.set_vertex_buffer(0, self.raw_indicator_buffer.slice(..));
-        //     rpass.set_vertex_buffer(1, self.square_vertex_buffer.slice(..));
-        //     rpass.draw(0..6, 0..((MAP_FIDELITY * MAP_FIDELITY) as u32));
-        // }
-        // command_encoder.pop_debug_group();
+        if let Some(recorder) = recorder.as_ref() {
+            command_encoder.push_debug_group("record");
+            recorder.copy(&mut command_encoder);
+            recorder.show(&mut command_encoder, view);
+            command_encoder.pop_debug_group();
+        }
 
         // update frame count
         self.frame_num += 1;
 
         // done
         queue.submit(Some(command_encoder.finish()));
+
+        // read the frame back once the GPU is done with it
+        if let Some(mut active) = recorder {
+            match active.write_frame(device) {
+                Ok(()) if !active.is_done() => recorder = Some(active),
+                Ok(()) => {
+                    recorder = None;
+                    match active.finish() {
+                        Ok(()) => println!("Recording finished"),
+                        Err(e) => eprintln!("Recording failed: {}", e),
+                    }
+                }
+                Err(e) => {
+                    recorder = None;
+                    eprintln!("Recording failed: {}", e);
+                }
+            }
+        }
+        self.recorder = recorder;
+
+        let step = self.frame_num * SIMULATION_ITTERATIONS;
+
+        // reduce the map and particles into a row of statistics when one is due
+        if let Some(log) = self.metrics.as_mut() {
+            if (step - SIMULATION_ITTERATIONS) / self.metrics_every != step / self.metrics_every {
+                let measurement = self.simulation.measure(device, queue);
+                if let Err(e) = log.write(&measurement) {
+                    eprintln!("Writing metrics failed: {}", e);
+                    self.metrics = None;
+                }
+            }
+        }
+
+        // save a NumPy snapshot if one was asked for or is due
+        if self.export_requested || self.exporter.is_due(step - SIMULATION_ITTERATIONS, step) {
+            self.export_requested = false;
+            let map = self.simulation.read_map(device, queue);
+            let particles = self.simulation.read_particles(device, queue);
+            match self.exporter.export(&map, self.simulation.config().map_fidelity as usize, &particles, step) {
+                Ok(path) => println!("Saved step {} to {}", step, path.display()),
+                Err(e) => eprintln!("Export failed: {}", e),
+            }
+        }
+
+        // extract the trail network as a graph if one was asked for
+        if self.network_requested {
+            self.network_requested = false;
+            let map = self.simulation.read_map(device, queue);
+            let network = network::Network::extract(&map, self.simulation.config().map_fidelity, self.network_threshold);
+            println!(
+                "Network at step {}: {} nodes, {} edges, {} junctions, {} components, length {:.3}",
+                step, network.nodes.len(), network.edges.len(), network.junctions(), network.components(), network.total_length()
+            );
+            if self.food.len() > 1 {
+                let connectivity = network.connectivity(&self.food);
+                println!(
+                    "Food sources: {} of {} pairs connected, mean path length {:.3}",
+                    connectivity.connected_pairs, connectivity.pairs, connectivity.mean_path_length
+                );
+            }
+            match network.save(self.exporter.dir(), step) {
+                Ok(path) => println!("Saved network to {}", path.display()),
+                Err(e) => eprintln!("Saving the network failed: {}", e),
+            }
+        }
+
+        // the same seed and config give the same hash on every deterministic run
+        if self.hash_frame == Some(self.frame_num) {
+            println!("Run hash {:016x} after {} steps", self.simulation.run_hash(device, queue), step);
+        }
+    }
+}
+
+/// runs every combination of --sweep off screen, without the framework
+#[cfg(not(target_arch = "wasm32"))]
+fn run_sweep(options: &cli::Options) {
+    let sweep = particles::sweep::Sweep {
+        base: base_config(options),
+        ranges: options.sweep.clone(),
+        steps: options.sweep_steps,
+        metrics_every: options.metrics_every,
+        dir: options.sweep_dir.clone(),
+    };
+    let result = particles::util::request_headless_device_on(&options.adapter).and_then(|(device, queue)| sweep.run(&device, &queue));
+    match result {
+        Ok(results) => println!("Saved {} runs to {}", results.len(), sweep.dir.display()),
+        Err(e) => exit_with(&e),
     }
 }
 
 /// run State
 fn main() {
-    framework::run::<State>("PARTICLES");
+    let options = cli::options();
+
+    // Listing adapters, sweeps and headless runs need the native framework
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if options.list_adapters {
+            framework::list_adapters::<State>(options.adapter.backends());
+            return;
+        }
+        if !options.sweep.is_empty() {
+            run_sweep(options);
+            return;
+        }
+
+        let result = if options.headless {
+            framework::run_headless::<State>(options.width, options.height, options.frames, &options.adapter)
+        } else {
+            framework::run::<State>("PARTICLES", &options.adapter)
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // The web build reports setup errors to the console itself
+    #[cfg(target_arch = "wasm32")]
+    framework::run::<State>("PARTICLES", &options.adapter);
 }
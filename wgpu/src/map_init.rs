@@ -0,0 +1,134 @@
+//! Initial contents of the trail map.
+//!
+//! The map used to start empty, guiding it with an image or breaking its
+//! symmetry with noise gives the particles something to follow from step one.
+
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use rand::{ distributions::Uniform, rngs::StdRng, SeedableRng, Rng};
+use std::str::FromStr;
+
+use crate::spawn::{arguments, DensityMap};
+
+/// Values written to every cell, noise is drawn from `Config::seed`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum MapInit {
+    /// All zeros
+    #[default]
+    Empty,
+    /// Independent values in `[0, amplitude]`
+    Uniform { amplitude: f32 },
+    /// Perlin noise with features roughly `1 / scale` wide, in `[0, amplitude]`
+    Perlin { scale: f32, amplitude: f32 },
+    /// Fractal Brownian motion, `octaves` layers of Perlin noise
+    Fbm { scale: f32, amplitude: f32, octaves: u32 },
+    /// Brightness of an image stretched over the map, scaled by `amplitude`
+    Image { image: DensityMap, amplitude: f32 },
+}
+
+/// Noise in `[-1, 1]` mapped to `[0, amplitude]`
+fn to_trail(value: f64, amplitude: f32) -> f32 {
+    ((value as f32 + 1.0) * 0.5).clamp(0.0, 1.0) * amplitude
+}
+
+fn fbm(scale: f32, octaves: u32, seed: u64, points: impl Iterator<Item = [f64; 2]>) -> impl Iterator<Item = f64> {
+    let fbm = Fbm::new()
+        .set_seed(seed as u32)
+        .set_octaves(octaves as usize)
+        .set_frequency(scale as f64);
+    points.map(move |p| fbm.get(p))
+}
+
+impl MapInit {
+
+    /// `map_fidelity` rows of `map_fidelity` cells, row 0 at the top
+    pub fn generate(&self, map_fidelity: u32, seed: u64) -> Vec<f32> {
+
+        let n = map_fidelity as usize;
+
+        // Centre of every cell in clip space, in map order
+        let centres = (0..n * n).map(|i| {
+            let column = (i % n) as f64 + 0.5;
+            let row = (i / n) as f64 + 0.5;
+            [column / n as f64 * 2.0 - 1.0, 1.0 - row / n as f64 * 2.0]
+        });
+
+        match self {
+            MapInit::Empty => vec![0.0f32; n * n],
+            MapInit::Uniform { amplitude } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let unif = Uniform::new_inclusive(0.0f32, *amplitude);
+                (0..n * n).map(|_| rng.sample(unif)).collect()
+            }
+            // A single octave of fBm is plain Perlin noise, and takes the scale as its frequency
+            MapInit::Perlin { scale, amplitude } => fbm(*scale, 1, seed, centres).map(|v| to_trail(v, *amplitude)).collect(),
+            MapInit::Fbm { scale, amplitude, octaves } => fbm(*scale, *octaves, seed, centres).map(|v| to_trail(v, *amplitude)).collect(),
+            MapInit::Image { image, amplitude } => {
+                // Nearest pixel to every cell
+                (0..n * n).map(|i| {
+                    let column = (i % n) * image.width / n;
+                    let row = (i / n) * image.height / n;
+                    image.values.get(row * image.width + column).copied().unwrap_or(0.0) * amplitude
+                }).collect()
+            }
+        }
+    }
+}
+
+/// `value` if it can scale the map, negative amplitudes and NaN are refused here
+/// rather than panicking or poisoning the map once it is generated
+fn checked_amplitude(value: f32) -> Result<f32, String> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("amplitude must be a finite number of at least 0, got {}", value))
+    }
+}
+
+/// Parses `empty`, `uniform[:amplitude]`, `perlin[:scale,amplitude]`,
+/// `fbm[:scale,amplitude,octaves]` and `image:FILE.png[,amplitude]`, the image is
+/// loaded right away
+impl FromStr for MapInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = match s.split_once(':') {
+            Some((name, args)) => (name, Some(args)),
+            None => (s, None),
+        };
+        Ok(match name {
+            "empty" => {
+                arguments(args, &[])?;
+                MapInit::Empty
+            }
+            "uniform" => {
+                let a = arguments(args, &[0.5])?;
+                MapInit::Uniform { amplitude: checked_amplitude(a[0])? }
+            }
+            "perlin" => {
+                let a = arguments(args, &[4.0, 0.5])?;
+                MapInit::Perlin { scale: a[0], amplitude: checked_amplitude(a[1])? }
+            }
+            "fbm" => {
+                let a = arguments(args, &[2.0, 0.5, 6.0])?;
+                if a[2] < 1.0 || a[2].fract() != 0.0 || a[2] > Fbm::MAX_OCTAVES as f32 {
+                    return Err(format!("octaves must be a whole number from 1 to {}, got {}", Fbm::MAX_OCTAVES, a[2]));
+                }
+                MapInit::Fbm { scale: a[0], amplitude: checked_amplitude(a[1])?, octaves: a[2] as u32 }
+            }
+            "image" => {
+                let args = args.ok_or("image needs a file, e.g. image:guide.png")?;
+                // A trailing number is the amplitude, anything else is part of the path
+                let (path, amplitude) = match args.rsplit_once(',') {
+                    Some((path, amplitude)) => match amplitude.trim().parse::<f32>() {
+                        Ok(value) => (path, checked_amplitude(value)?),
+                        Err(_) => (args, 1.0),
+                    },
+                    None => (args, 1.0),
+                };
+                let image = DensityMap::from_png(path).map_err(|e| format!("unable to load {}: {}", path, e))?;
+                MapInit::Image { image, amplitude }
+            }
+            _ => return Err(format!("unknown map init {}, expected empty, uniform, perlin, fbm or image", name)),
+        })
+    }
+}
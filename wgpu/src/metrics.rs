@@ -0,0 +1,242 @@
+//! Statistics of the trail network, reduced on the GPU so only a handful of
+//! floats come back per measurement.
+//!
+//! Written as a CSV time series, plotting `trail_mass` or `trail_variance`
+//! against `step` shows when a choice of `fade_power` and `erase_power` has
+//! reached its steady state.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::simulation::{constants_bindings, map_bindings, particle_bindings};
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_layout, make_pipeline_layout, make_shader, shader, dispatch_grid, PipelineBindings};
+
+/// One row of the time series
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Measurement {
+    pub step: u32,
+    pub particles: u32,
+    /// Sum of every cell of the map
+    pub trail_mass: f32,
+    pub trail_mean: f32,
+    pub trail_variance: f32,
+    /// Fraction of cells holding at least `cell_impact`, one deposit's worth
+    pub occupied: f32,
+    pub mean_speed: f32,
+    /// 1 when every cell holds the population's mix of preferences, 0 when
+    /// every cell holds a single one. One minus the share of the variance of
+    /// `preference` that lies between cells
+    pub mixing: f32,
+}
+
+impl Measurement {
+    pub const CSV_HEADER: &'static str = "step,particles,trail_mass,trail_mean,trail_variance,occupied,mean_speed,mixing";
+
+    /// Turns the two totals of `metrics.wgsl` into averages
+    pub(crate) fn from_totals(step: u32, cells: u32, totals: [f32; 8]) -> Self {
+        let [count, speed, preference, preference_squared, trail, trail_squared, occupied, between] = totals;
+
+        let cells = cells as f32;
+        let trail_mean = trail / cells;
+
+        // Variance of the preferences in total and of their per cell means,
+        // both as sums of squares about the population mean
+        let mean_preference = if count > 0.0 { preference / count } else { 0.0 };
+        let total = preference_squared - count * mean_preference * mean_preference;
+        let between = between - count * mean_preference * mean_preference;
+        let mixing = if total > 1e-6 { (1.0 - between / total).clamp(0.0, 1.0) } else { 1.0 };
+
+        Measurement {
+            step,
+            particles: count as u32,
+            trail_mass: trail,
+            trail_mean,
+            trail_variance: (trail_squared / cells - trail_mean * trail_mean).max(0.0),
+            occupied: occupied / cells,
+            mean_speed: if count > 0.0 { speed / count } else { 0.0 },
+            mixing,
+        }
+    }
+
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.step, self.particles, self.trail_mass, self.trail_mean, self.trail_variance, self.occupied, self.mean_speed, self.mixing
+        )
+    }
+}
+
+/// Appends measurements to a CSV file, header first
+pub struct MetricsLog {
+    writer: BufWriter<File>,
+}
+
+impl MetricsLog {
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", Measurement::CSV_HEADER)?;
+        Ok(MetricsLog { writer })
+    }
+
+    /// Flushed every row so the file can be plotted while the run goes on
+    pub fn write(&mut self, measurement: &Measurement) -> io::Result<()> {
+        writeln!(self.writer, "{}", measurement.csv_row())?;
+        self.writer.flush()
+    }
+}
+
+/// The reduction kernels and their scratch buffers
+pub(crate) struct Metrics {
+
+    // Per cell particle count and fixed point preference sum, left empty by `measure_map`,
+    // and the per workgroup partials. Only held so the bind group's buffers live on
+    _cell_buffers: [wgpu::Buffer; 2],
+    _partial_buffers: [wgpu::Buffer; 2],
+    totals_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    particles_pipeline: wgpu::ComputePipeline,
+    map_pipeline: wgpu::ComputePipeline,
+    finish_pipeline: wgpu::ComputePipeline,
+
+    particle_workgroups: (u32, u32),
+    map_workgroups: (u32, u32),
+}
+
+/// Partial sums of the particle and map workgroups of a grid of at most `max_workgroups` wide
+fn partials(config: &Config, max_workgroups: u32) -> (u32, u32) {
+    let (x, y) = dispatch_grid(config.max_particles, max_workgroups);
+    let particle_partials = x * y;
+    let (x, y) = dispatch_grid(config.map_fidelity * config.map_fidelity, max_workgroups);
+    (particle_partials, x * y)
+}
+
+/// Per cell counts and sums, the partials of each workgroup and the final totals
+fn metrics_bindings(config: &Config, max_workgroups: u32) -> Vec<wgpu::BindingType> {
+    let _f = std::mem::size_of::<f32>() as u32;
+    let _partial = 4 * _f;
+    let cells = config.map_fidelity * config.map_fidelity;
+    let (particle_partials, map_partials) = partials(config, max_workgroups);
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: false }, &[
+        _f * cells,
+        _f * cells,
+        _partial * particle_partials,
+        _partial * map_partials,
+        2 * _partial,
+    ])
+}
+
+/// The three reductions built in `Metrics::new`, sharing one layout
+pub(crate) fn pipeline_bindings(config: &Config, max_workgroups: u32) -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("metrics.wgsl", &["measure_particles", "measure_map", "finish"], vec![
+            constants_bindings(),
+            particle_bindings(config),
+            map_bindings(config),
+            metrics_bindings(config, max_workgroups),
+        ]),
+    ]
+}
+
+impl Metrics {
+
+    pub fn new(device: &wgpu::Device, config: &Config, binding_constants_compute: &wgpu::BindGroupLayout, binding_particles_compute: &wgpu::BindGroupLayout, binding_map_compute: &wgpu::BindGroupLayout) -> Self {
+
+        let shader = make_shader(device, shader!("metrics.wgsl"));
+
+        let cells = config.map_fidelity * config.map_fidelity;
+
+        // Every slot is covered so each partial is rewritten on every measurement,
+        // there is one per workgroup of the grid including any spare at the end
+        let max_workgroups = device.limits().max_compute_workgroups_per_dimension;
+        let particle_workgroups = dispatch_grid(config.max_particles, max_workgroups);
+        let map_workgroups = dispatch_grid(cells, max_workgroups);
+        let (particle_partials, map_partials) = partials(config, max_workgroups);
+
+        // Setup Bind Layouts
+
+        let binding_metrics = make_layout(device, wgpu::ShaderStages::COMPUTE, &metrics_bindings(config, max_workgroups));
+
+        let pipeline_layout = make_pipeline_layout(device, &[
+            binding_constants_compute,
+            binding_particles_compute,
+            binding_map_compute,
+            &binding_metrics,
+        ]);
+
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point,
+        });
+        let particles_pipeline = pipeline("measure_particles");
+        let map_pipeline = pipeline("measure_map");
+        let finish_pipeline = pipeline("finish");
+
+
+        // Buffers, zero is zero whether read as f32 or u32
+
+        let cell_buffers = [
+            make_buffer(device, &vec![0.0f32; cells as usize]),
+            make_buffer(device, &vec![0.0f32; cells as usize]),
+        ];
+        let partial_buffers = [
+            make_buffer(device, &vec![0.0f32; 4 * particle_partials as usize]),
+            make_buffer(device, &vec![0.0f32; 4 * map_partials as usize]),
+        ];
+        let totals_buffer = make_buffer(device, &[0.0f32; 8]);
+
+        let bind_group = make_bind_group(device, &binding_metrics, &[
+            &cell_buffers[0],
+            &cell_buffers[1],
+            &partial_buffers[0],
+            &partial_buffers[1],
+            &totals_buffer,
+        ]);
+
+        Metrics {
+            _cell_buffers: cell_buffers,
+            _partial_buffers: partial_buffers,
+            totals_buffer,
+            bind_group,
+            particles_pipeline,
+            map_pipeline,
+            finish_pipeline,
+            particle_workgroups,
+            map_workgroups,
+        }
+    }
+
+    /// Reduces the live particles and the map, then reads the two totals back
+    pub fn measure(&self, device: &wgpu::Device, queue: &wgpu::Queue, const_bind_compute_group: &wgpu::BindGroup, particle_bind_group: &wgpu::BindGroup, map_bind_group: &wgpu::BindGroup) -> [f32; 8] {
+
+        let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        command_encoder.push_debug_group("compute metrics");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_bind_group(0, const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, particle_bind_group, &[]);
+            cpass.set_bind_group(2, map_bind_group, &[]);
+            cpass.set_bind_group(3, &self.bind_group, &[]);
+
+            cpass.set_pipeline(&self.particles_pipeline);
+            cpass.dispatch(self.particle_workgroups.0, self.particle_workgroups.1, 1);
+
+            cpass.set_pipeline(&self.map_pipeline);
+            cpass.dispatch(self.map_workgroups.0, self.map_workgroups.1, 1);
+
+            cpass.set_pipeline(&self.finish_pipeline);
+            cpass.dispatch(1, 1, 1);
+        }
+        command_encoder.pop_debug_group();
+        queue.submit(Some(command_encoder.finish()));
+
+        let totals = crate::export::read_buffer(device, queue, &self.totals_buffer, 8);
+        totals.try_into().unwrap()
+    }
+}
@@ -0,0 +1,141 @@
+// Sums over the particles and the map, reduced a workgroup at a time into
+// partials and then by `finish` into two totals:
+//   totals[0] = particles, speed, preference, preference^2
+//   totals[1] = trail, trail^2, occupied cells, sum over cells of (cell preference)^2 / particles in cell
+
+@group(0) @binding(0) var<uniform> consts : SimulationConstants;
+@group(1) @binding(0) var<storage, read_write> particles : array<Particle>;
+@group(2) @binding(0) var<storage, read_write> map : array<f32>;
+@group(3) @binding(0) var<storage, read_write> cell_count : array<atomic<u32>>;
+@group(3) @binding(1) var<storage, read_write> cell_preference : array<atomic<u32>>;
+@group(3) @binding(2) var<storage, read_write> particle_partials : array<vec4<f32>>;
+@group(3) @binding(3) var<storage, read_write> map_partials : array<vec4<f32>>;
+@group(3) @binding(4) var<storage, read_write> totals : array<vec4<f32>, 2>;
+
+// Preference is summed per cell in fixed point, as there are no float atomics
+let PREFERENCE_SCALE = 1024.0;
+
+var<workgroup> scratch : array<vec4<f32>, WORKGROUP_SIZE>;
+
+fn get_cell_index (x : f32, y : f32) -> i32 {
+
+    let world_x = ( x + 1.0) / 2.0 * consts.mapFidelity;
+    let world_y = ( -y + 1.0) / 2.0 * consts.mapFidelity;
+
+    var index_x = floor( world_x );
+    var index_y = floor( world_y );
+
+    if (index_x < 0.0 ) { index_x = index_x + consts.mapFidelity; }
+    if (index_y < 0.0 ) { index_y = index_y + consts.mapFidelity; }
+    if (index_y > consts.mapFidelity - 1.0 ) { index_y = index_y - consts.mapFidelity; }
+    if (index_x > consts.mapFidelity - 1.0 ) { index_x = index_x - consts.mapFidelity; }
+
+    return i32(index_y * consts.mapFidelity + index_x);
+}
+
+// Tree sum of `scratch`, every invocation of the workgroup has to call it
+fn reduce (local : u32) {
+    workgroupBarrier();
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride = stride >> 1u) {
+        if (local < stride) {
+            scratch[local] = scratch[local] + scratch[local + stride];
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn measure_particles(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+
+    let index = invocation_index(global_invocation_id, num_workgroups);
+    let cells = i32(consts.mapFidelity * consts.mapFidelity);
+
+    var value = vec4<f32>(0.0);
+    if (index < consts.numParticles) {
+        let particle = particles[index];
+        let preference = clamp(particle.preference, 0.0, 1.0);
+        value = vec4<f32>(1.0, length(vec2<f32>(particle.velX, particle.velY)), preference, preference * preference);
+
+        let cell = get_cell_index(particle.posX, particle.posY);
+        if (cell >= 0 && cell < cells) {
+            atomicAdd(&cell_count[cell], 1u);
+            atomicAdd(&cell_preference[cell], u32(preference * PREFERENCE_SCALE));
+        }
+    }
+
+    scratch[local] = value;
+    reduce(local);
+    if (local == 0u) {
+        particle_partials[workgroup_id.x + workgroup_id.y * num_workgroups.x] = scratch[0];
+    }
+}
+
+// Runs after `measure_particles`, and empties the per cell sums for next time
+@compute
+@workgroup_size(64)
+fn measure_map(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+
+    let index = invocation_index(global_invocation_id, num_workgroups);
+
+    var value = vec4<f32>(0.0);
+    if (index < u32(consts.mapFidelity * consts.mapFidelity)) {
+        let trail = map[index];
+        let count = atomicExchange(&cell_count[index], 0u);
+        let preference = f32(atomicExchange(&cell_preference[index], 0u)) / PREFERENCE_SCALE;
+
+        // A cell counts as occupied once it holds a single deposit's worth of trail
+        var occupied = 0.0;
+        if (trail >= consts.cellImpact) {
+            occupied = 1.0;
+        }
+        var between = 0.0;
+        if (count > 0u) {
+            between = preference * preference / f32(count);
+        }
+        value = vec4<f32>(trail, trail * trail, occupied, between);
+    }
+
+    scratch[local] = value;
+    reduce(local);
+    if (local == 0u) {
+        map_partials[workgroup_id.x + workgroup_id.y * num_workgroups.x] = scratch[0];
+    }
+}
+
+// A single workgroup summing the partials of both kernels
+@compute
+@workgroup_size(64)
+fn finish(@builtin(local_invocation_index) local: u32) {
+
+    var sum = vec4<f32>(0.0);
+    for (var i = local; i < arrayLength(&particle_partials); i = i + WORKGROUP_SIZE) {
+        sum = sum + particle_partials[i];
+    }
+    scratch[local] = sum;
+    reduce(local);
+    if (local == 0u) {
+        totals[0] = scratch[0];
+    }
+    workgroupBarrier();
+
+    sum = vec4<f32>(0.0);
+    for (var i = local; i < arrayLength(&map_partials); i = i + WORKGROUP_SIZE) {
+        sum = sum + map_partials[i];
+    }
+    scratch[local] = sum;
+    reduce(local);
+    if (local == 0u) {
+        totals[1] = scratch[0];
+    }
+}
@@ -0,0 +1,473 @@
+//! The transport network the agents build, as a graph.
+//!
+//! The trail map is thresholded, thinned to lines one cell wide with the
+//! Zhang-Suen algorithm and walked: junctions and loose ends become nodes and
+//! the lines between them edges. The map wraps around like the simulation,
+//! so lines may run off one side and on at the other.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A junction, loose end or point on a closed loop
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    /// Position in simulation coordinates, y up like the particles
+    pub x: f32,
+    pub y: f32,
+    /// Edges meeting here, a loop counts twice
+    pub degree: u32,
+}
+
+/// A line of trail between two nodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// In simulation units, the map is 2 wide
+    pub length: f32,
+    /// Mean trail along the line
+    pub intensity: f32,
+}
+
+/// How well a set of food sources is joined up, each snapped to its nearest node
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connectivity {
+    pub pairs: usize,
+    pub connected_pairs: usize,
+    /// Mean shortest path along the network between the connected pairs
+    pub mean_path_length: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    pub map_fidelity: u32,
+    pub threshold: f32,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+// Cell classes while walking the skeleton
+const EMPTY: u8 = 0;
+const LINE: u8 = 1;
+const NODE: u8 = 2;
+
+// Neighbour order of `neighbours`, diagonal ones first when walking so
+// staircases are measured along the diagonal rather than around each step
+const WALK_ORDER: [usize; 8] = [1, 3, 5, 7, 0, 2, 4, 6];
+
+/// The 8 neighbours of cell `i` clockwise from the one above, wrapping around
+fn neighbours(n: usize, i: usize) -> [usize; 8] {
+    let (row, col) = (i / n, i % n);
+    let up = (row + n - 1) % n;
+    let down = (row + 1) % n;
+    let left = (col + n - 1) % n;
+    let right = (col + 1) % n;
+    [
+        up * n + col,
+        up * n + right,
+        row * n + right,
+        down * n + right,
+        down * n + col,
+        down * n + left,
+        row * n + left,
+        up * n + left,
+    ]
+}
+
+/// How many separate runs of set cells surround a cell, 1 at a line's end,
+/// 2 along it and 3 or more at a junction
+fn crossings(set: &[bool; 8]) -> usize {
+    (0..8).filter(|&k| !set[k] && set[(k + 1) % 8]).count()
+}
+
+fn around(mask: &[bool], n: usize, i: usize) -> [bool; 8] {
+    neighbours(n, i).map(|j| mask[j])
+}
+
+/// Zhang-Suen thinning, peels cells off the mask until only centre lines remain
+fn skeletonise(mask: &mut [bool], n: usize) {
+    loop {
+        let mut changed = false;
+        for pass in 0..2 {
+            let remove: Vec<usize> = (0..mask.len()).filter(|&i| {
+                if !mask[i] {
+                    return false;
+                }
+                // p2 to p9 of the paper, p2 above and clockwise from there
+                let p = around(mask, n, i);
+                let count = p.iter().filter(|&&b| b).count();
+                let (up, right, down, left) = (p[0], p[2], p[4], p[6]);
+                let sides = match pass {
+                    0 => !(right && down && (up || left)),
+                    _ => !(up && left && (right || down)),
+                };
+                (2..=6).contains(&count) && crossings(&p) == 1 && sides
+            }).collect();
+            changed |= !remove.is_empty();
+            for i in remove {
+                mask[i] = false;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Distance between neighbouring cells
+fn step(n: usize, a: usize, b: usize) -> f32 {
+    if a / n == b / n || a % n == b % n { 1.0 } else { std::f32::consts::SQRT_2 }
+}
+
+struct Walker<'a> {
+    n: usize,
+    map: &'a [f32],
+    kind: Vec<u8>,
+    node_of: Vec<usize>,
+    visited: Vec<bool>,
+    edges: Vec<Edge>,
+}
+
+impl Walker<'_> {
+
+    /// Follows a line from node cell `start` through `first` until it reaches a node,
+    /// adding the edge. A line that peters out gets a new node at its end
+    fn trace(&mut self, start: usize, first: usize, node_cells: &mut Vec<Vec<usize>>) {
+        let n = self.n;
+        let from = self.node_of[start];
+
+        let mut length = step(n, start, first);
+        let mut trail = self.map[first];
+        let mut cells = 1;
+        self.visited[first] = true;
+
+        let (mut previous, mut current) = (start, first);
+        loop {
+            let around = neighbours(n, current);
+
+            // Any node ends the line, except the one it left in its first few cells
+            let end = around.iter().copied().find(|&j| {
+                self.kind[j] == NODE && j != previous && !(cells < 3 && self.node_of[j] == from)
+            });
+            if let Some(end) = end {
+                length += step(n, current, end);
+                self.edges.push(Edge { from, to: self.node_of[end], length, intensity: trail / cells as f32 });
+                return;
+            }
+
+            // Cells next to the previous one are other lines leaving the same
+            // junction, or a corner, so they only come after the rest
+            let behind = neighbours(n, previous);
+            let open = |j: &usize| self.kind[*j] == LINE && !self.visited[*j];
+            let candidates = WALK_ORDER.iter().map(|&k| around[k]).filter(open);
+            let next = candidates.clone().find(|j| !behind.contains(j)).or_else(|| candidates.clone().next());
+            match next {
+                Some(next) => {
+                    // The corner cells of a staircase that the diagonal step cuts
+                    let beyond = neighbours(n, next);
+                    for j in around {
+                        if self.kind[j] == LINE && beyond.contains(&j) {
+                            self.visited[j] = true;
+                        }
+                    }
+                    length += step(n, current, next);
+                    trail += self.map[next];
+                    cells += 1;
+                    self.visited[next] = true;
+                    previous = current;
+                    current = next;
+                }
+                None => {
+                    let to = node_cells.len();
+                    node_cells.push(vec![current]);
+                    self.kind[current] = NODE;
+                    self.node_of[current] = to;
+                    self.edges.push(Edge { from, to, length, intensity: trail / cells as f32 });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Traces every line leaving the node cell `i`
+    fn trace_from(&mut self, i: usize, node_cells: &mut Vec<Vec<usize>>) {
+        for j in neighbours(self.n, i) {
+            if self.kind[j] == LINE && !self.visited[j] {
+                self.trace(i, j, node_cells);
+            }
+        }
+    }
+}
+
+// Dijkstra's queue entry, nearest first
+struct Visit(f32, usize);
+
+impl PartialEq for Visit {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl Eq for Visit {}
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+impl Network {
+
+    /// Extracts the network from a trail map, `map_fidelity` rows with row 0 at the
+    /// top as read back from the GPU. Cells holding at least `threshold` are trail
+    pub fn extract(map: &[f32], map_fidelity: u32, threshold: f32) -> Self {
+        let n = map_fidelity as usize;
+        assert_eq!(map.len(), n * n, "map does not match map_fidelity");
+
+        let mut mask: Vec<bool> = map.iter().map(|&v| v >= threshold).collect();
+        skeletonise(&mut mask, n);
+
+        // Lone cells are noise rather than network
+        let kind: Vec<u8> = (0..n * n).map(|i| {
+            if !mask[i] {
+                return EMPTY;
+            }
+            let p = around(&mask, n, i);
+            match crossings(&p) {
+                0 if !p.contains(&true) => EMPTY,
+                2 => LINE,
+                _ => NODE,
+            }
+        }).collect();
+
+        // Touching node cells make up one node, junctions are often a few cells across
+        let mut node_of = vec![usize::MAX; n * n];
+        let mut node_cells: Vec<Vec<usize>> = Vec::new();
+        for i in 0..n * n {
+            if kind[i] != NODE || node_of[i] != usize::MAX {
+                continue;
+            }
+            let id = node_cells.len();
+            let mut cells = vec![i];
+            node_of[i] = id;
+            let mut k = 0;
+            while k < cells.len() {
+                for j in neighbours(n, cells[k]) {
+                    if kind[j] == NODE && node_of[j] == usize::MAX {
+                        node_of[j] = id;
+                        cells.push(j);
+                    }
+                }
+                k += 1;
+            }
+            node_cells.push(cells);
+        }
+
+        let mut walker = Walker { n, map, kind, node_of, visited: vec![false; n * n], edges: Vec::new() };
+
+        // Lines between nodes, the list grows as dead ends are found
+        let mut node = 0;
+        while node < node_cells.len() {
+            for i in node_cells[node].clone() {
+                walker.trace_from(i, &mut node_cells);
+            }
+            node += 1;
+        }
+
+        // What is left are closed loops without a junction, each gets a node of its own
+        for i in 0..n * n {
+            if walker.kind[i] == LINE && !walker.visited[i] {
+                walker.kind[i] = NODE;
+                walker.node_of[i] = node_cells.len();
+                node_cells.push(vec![i]);
+                walker.trace_from(i, &mut node_cells);
+            }
+        }
+
+        let cell_size = 2.0 / n as f32;
+        let mut nodes: Vec<Node> = node_cells.iter().map(|cells| {
+            let row = cells.iter().map(|&i| (i / n) as f32).sum::<f32>() / cells.len() as f32;
+            let col = cells.iter().map(|&i| (i % n) as f32).sum::<f32>() / cells.len() as f32;
+            Node { x: (col + 0.5) * cell_size - 1.0, y: 1.0 - (row + 0.5) * cell_size, degree: 0 }
+        }).collect();
+
+        let mut edges = walker.edges;
+        for edge in edges.iter_mut() {
+            edge.length *= cell_size;
+            nodes[edge.from].degree += 1;
+            nodes[edge.to].degree += 1;
+        }
+
+        // Specks thinned down to a cluster of cells with no line leaving them
+        let mut index = vec![usize::MAX; nodes.len()];
+        let mut kept = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            if node.degree > 0 {
+                index[i] = kept;
+                kept += 1;
+            }
+        }
+        nodes.retain(|node| node.degree > 0);
+        for edge in edges.iter_mut() {
+            edge.from = index[edge.from];
+            edge.to = index[edge.to];
+        }
+
+        Network { map_fidelity, threshold, nodes, edges }
+    }
+
+    /// Length of every edge together, in simulation units
+    pub fn total_length(&self) -> f32 {
+        self.edges.iter().fold(0.0, |total, e| total + e.length)
+    }
+
+    /// Nodes where three or more lines meet
+    pub fn junctions(&self) -> usize {
+        self.nodes.iter().filter(|n| n.degree >= 3).count()
+    }
+
+    /// Separate pieces of network
+    pub fn components(&self) -> usize {
+        let mut parent: Vec<usize> = (0..self.nodes.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for edge in &self.edges {
+            let (a, b) = (root(&mut parent, edge.from), root(&mut parent, edge.to));
+            parent[a] = b;
+        }
+        (0..self.nodes.len()).filter(|&i| root(&mut parent, i) == i).count()
+    }
+
+    /// Node closest to a point, distances wrapping around the map
+    pub fn nearest_node(&self, x: f32, y: f32) -> Option<usize> {
+        let wrapped = |d: f32| { let d = d.abs() % 2.0; d.min(2.0 - d) };
+        let distance = |n: &Node| wrapped(n.x - x).powi(2) + wrapped(n.y - y).powi(2);
+        (0..self.nodes.len()).min_by(|&a, &b| distance(&self.nodes[a]).total_cmp(&distance(&self.nodes[b])))
+    }
+
+    /// Shortest path lengths from `source` to every node, infinite where unreachable
+    fn distances(&self, source: usize) -> Vec<f32> {
+        let mut adjacent = vec![Vec::new(); self.nodes.len()];
+        for edge in &self.edges {
+            adjacent[edge.from].push((edge.to, edge.length));
+            adjacent[edge.to].push((edge.from, edge.length));
+        }
+
+        let mut distance = vec![f32::INFINITY; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        distance[source] = 0.0;
+        queue.push(Visit(0.0, source));
+        while let Some(Visit(d, node)) = queue.pop() {
+            if d > distance[node] {
+                continue;
+            }
+            for &(next, length) in &adjacent[node] {
+                if d + length < distance[next] {
+                    distance[next] = d + length;
+                    queue.push(Visit(d + length, next));
+                }
+            }
+        }
+        distance
+    }
+
+    /// How many pairs of food sources, given in simulation coordinates, the network joins
+    pub fn connectivity(&self, sources: &[[f32; 2]]) -> Connectivity {
+        let snapped: Vec<Option<usize>> = sources.iter().map(|s| self.nearest_node(s[0], s[1])).collect();
+
+        let mut pairs = 0;
+        let mut connected_pairs = 0;
+        let mut total = 0.0;
+        for (i, a) in snapped.iter().enumerate() {
+            let distances = a.map(|a| self.distances(a));
+            for b in &snapped[i + 1..] {
+                pairs += 1;
+                if let (Some(distances), Some(b)) = (&distances, b) {
+                    if distances[*b].is_finite() {
+                        connected_pairs += 1;
+                        total += distances[*b];
+                    }
+                }
+            }
+        }
+
+        Connectivity {
+            pairs,
+            connected_pairs,
+            mean_path_length: if connected_pairs > 0 { total / connected_pairs as f32 } else { 0.0 },
+        }
+    }
+
+    /// GraphML with node positions and degrees and edge lengths and intensities
+    pub fn write_graphml<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        writeln!(writer, r#"  <key id="x" for="node" attr.name="x" attr.type="float"/>"#)?;
+        writeln!(writer, r#"  <key id="y" for="node" attr.name="y" attr.type="float"/>"#)?;
+        writeln!(writer, r#"  <key id="degree" for="node" attr.name="degree" attr.type="int"/>"#)?;
+        writeln!(writer, r#"  <key id="length" for="edge" attr.name="length" attr.type="float"/>"#)?;
+        writeln!(writer, r#"  <key id="intensity" for="edge" attr.name="intensity" attr.type="float"/>"#)?;
+        writeln!(writer, r#"  <graph id="trails" edgedefault="undirected">"#)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"    <node id="n{}"><data key="x">{}</data><data key="y">{}</data><data key="degree">{}</data></node>"#,
+                i, node.x, node.y, node.degree
+            )?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                r#"    <edge source="n{}" target="n{}"><data key="length">{}</data><data key="intensity">{}</data></edge>"#,
+                edge.from, edge.to, edge.length, edge.intensity
+            )?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")?;
+        writer.flush()
+    }
+
+    /// JSON with the nodes, edges and summary metrics, edges refer to nodes by index
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, r#"  "map_fidelity": {},"#, self.map_fidelity)?;
+        writeln!(writer, r#"  "threshold": {},"#, self.threshold)?;
+        writeln!(writer, r#"  "total_length": {},"#, self.total_length())?;
+        writeln!(writer, r#"  "junctions": {},"#, self.junctions())?;
+        writeln!(writer, r#"  "components": {},"#, self.components())?;
+        writeln!(writer, r#"  "nodes": ["#)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let comma = if i + 1 < self.nodes.len() { "," } else { "" };
+            writeln!(writer, r#"    {{"x": {}, "y": {}, "degree": {}}}{}"#, node.x, node.y, node.degree, comma)?;
+        }
+        writeln!(writer, "  ],")?;
+        writeln!(writer, r#"  "edges": ["#)?;
+        for (i, edge) in self.edges.iter().enumerate() {
+            let comma = if i + 1 < self.edges.len() { "," } else { "" };
+            writeln!(writer, r#"    {{"source": {}, "target": {}, "length": {}, "intensity": {}}}{}"#, edge.from, edge.to, edge.length, edge.intensity, comma)?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+
+    /// Writes `network_{step}.graphml` and `network_{step}.json` into `dir`
+    pub fn save(&self, dir: &Path, step: u32) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("network_{:08}.graphml", step));
+        self.write_graphml(BufWriter::new(File::create(&path)?))?;
+        self.write_json(BufWriter::new(File::create(path.with_extension("json"))?))?;
+        Ok(path)
+    }
+}
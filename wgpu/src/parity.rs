@@ -0,0 +1,96 @@
+//! Runs the shaders and the CPU reference side by side and measures how far
+//! they drift apart.
+//!
+//! Both start from the same `Config`, so the same particles and map. The GPU
+//! races on the map where the reference does not, so small map differences
+//! are expected, anything systematic points at the shaders and
+//! `reference.rs` disagreeing on wrapping, sensing or clamping.
+
+use std::fmt;
+
+use crate::config::Config;
+use crate::particle::Particle;
+use crate::reference::Reference;
+use crate::simulation::Simulation;
+
+/// The columns of `Simulation::read_particles`, in order
+pub const PARTICLE_FIELDS: [&str; Particle::FLOATS] = ["pos_x", "pos_y", "sense_distance", "vel_x", "vel_y", "preference", "energy"];
+
+/// Absolute difference between the two paths over one field
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub field: &'static str,
+    pub max: f32,
+    pub mean: f32,
+}
+
+/// Every field after one step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parity {
+    pub step: u32,
+    /// One per particle field, then `map`
+    pub differences: Vec<Difference>,
+}
+
+impl Parity {
+    pub fn field(&self, field: &str) -> Option<&Difference> {
+        self.differences.iter().find(|d| d.field == field)
+    }
+}
+
+impl fmt::Display for Parity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for d in &self.differences {
+            writeln!(f, "{:>6}  {:<16}{:>14.6e}{:>14.6e}", self.step, d.field, d.max, d.mean)?;
+        }
+        Ok(())
+    }
+}
+
+/// Values that are NaN on one side only count as infinitely far apart
+fn difference(field: &'static str, gpu: impl Iterator<Item = f32>, cpu: impl Iterator<Item = f32>) -> Difference {
+    let (mut max, mut sum, mut count) = (0.0f32, 0.0f64, 0usize);
+    for (a, b) in gpu.zip(cpu) {
+        let d = match (a.is_nan(), b.is_nan()) {
+            (true, true) => 0.0,
+            (false, false) => (a - b).abs(),
+            _ => f32::INFINITY,
+        };
+        max = max.max(d);
+        sum += d as f64;
+        count += 1;
+    }
+    Difference { field, max, mean: if count == 0 { 0.0 } else { (sum / count as f64) as f32 } }
+}
+
+/// Steps both paths `steps` times from `config`, comparing them after every step
+pub fn compare(device: &wgpu::Device, queue: &wgpu::Queue, config: Config, steps: u32) -> Result<Vec<Parity>, String> {
+
+    if config.lifecycle {
+        return Err("lifecycle is not modelled by the CPU reference".to_string());
+    }
+
+    // The reference runs what the simulation settled on, after `fit` and the capacity
+    let mut simulation = Simulation::new(device, queue, config)?;
+    let mut reference = Reference::new(simulation.config().clone());
+
+    let mut results = Vec::new();
+    for _ in 0..steps {
+        let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        simulation.step(&mut command_encoder);
+        queue.submit(Some(command_encoder.finish()));
+        reference.step();
+
+        let gpu_particles = simulation.read_particles(device, queue);
+        let cpu_particles: &[f32] = bytemuck::cast_slice(reference.particles());
+
+        let column = |flat: &[f32], i: usize| flat.iter().skip(i).step_by(Particle::FLOATS).copied().collect::<Vec<_>>();
+        let mut differences: Vec<Difference> = PARTICLE_FIELDS.iter().enumerate()
+            .map(|(i, field)| difference(field, column(&gpu_particles, i).into_iter(), column(cpu_particles, i).into_iter()))
+            .collect();
+        differences.push(difference("map", simulation.read_map(device, queue).into_iter(), reference.map().iter().copied()));
+
+        results.push(Parity { step: reference.steps(), differences });
+    }
+    Ok(results)
+}
@@ -0,0 +1,29 @@
+use bytemuck::{Pod, Zeroable};
+
+/// A single agent, laid out to match `Particle` in the compute shaders.
+///
+/// Every field is a plain f32 so Rust and the WGSL storage buffers agree on a
+/// 28 byte stride without padding, and the exported `(N, 7)` arrays keep their
+/// column order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct Particle {
+    pub pos_x: f32,
+    pub pos_y: f32,
+    // How far ahead the agent looks
+    pub sense_distance: f32,
+    pub vel_x: f32,
+    pub vel_y: f32,
+    // Trail density the agent steers towards, also its species
+    pub preference: f32,
+    // Only used with `lifecycle`, trail eaten minus what the agent burned
+    pub energy: f32,
+}
+
+impl Particle {
+    /// f32 per particle, the second dimension of exported arrays
+    pub const FLOATS: usize = 7;
+
+    /// Bytes per particle in the storage buffers
+    pub const SIZE: usize = std::mem::size_of::<Particle>();
+}
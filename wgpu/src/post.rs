@@ -0,0 +1,370 @@
+use std::mem;
+use std::str::FromStr;
+
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_layout, make_pipeline_layout, make_shader, PipelineBindings};
+
+// Floating point target the map is drawn into when HDR is enabled
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// How many times the bright pass is halved before being blurred back up
+const BLOOM_LEVELS: usize = 5;
+
+/// Curve used to bring the HDR image back into displayable range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapping {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Clamp,
+        }
+    }
+}
+
+impl FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "clamp" => ToneMapping::Clamp,
+            "reinhard" => ToneMapping::Reinhard,
+            "aces" => ToneMapping::Aces,
+            _ => return Err(format!("unknown tone mapping {}, expected clamp, reinhard or aces", s)),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub exposure: f32,
+    pub bloom_threshold: f32,
+    pub bloom_strength: f32,
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            exposure: 1.0,
+            bloom_threshold: 0.8,
+            bloom_strength: 0.6,
+            tone_mapping: ToneMapping::Aces,
+        }
+    }
+}
+
+impl Settings {
+    /// Data laid out to match `PostConstants` in post.wgsl
+    fn uniform_data(&self) -> [f32; 4] {
+        [
+            self.exposure,
+            self.bloom_threshold,
+            self.bloom_strength,
+            self.tone_mapping as u32 as f32,
+        ]
+    }
+}
+
+struct Target {
+    view: wgpu::TextureView,
+    // Bind group sampling this target as the source of the next pass
+    source: wgpu::BindGroup,
+}
+
+/// HDR render target followed by a bloom chain and a tone mapping pass.
+///
+/// The map is drawn into `hdr_view`, `run` then thresholds it, halves it
+/// `BLOOM_LEVELS` times, blurs it back up and composites the glow over the
+/// original before tone mapping into the output view.
+pub struct PostProcess {
+    settings: Settings,
+    dirty: bool,
+
+    constants_buffer: wgpu::Buffer,
+    constants_bind_group: wgpu::BindGroup,
+
+    source_layout: wgpu::BindGroupLayout,
+    bloom_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+
+    hdr: Target,
+    levels: Vec<Target>,
+    composite_bind_group: wgpu::BindGroup,
+
+    threshold_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+fn make_target(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, sampler: &wgpu::Sampler, width: u32, height: u32) -> Target {
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d { width: width.max(1), height: height.max(1), depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let source = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(sampler) },
+        ],
+    });
+
+    Target { view, source }
+}
+
+/// The `Settings` uniform
+fn constants_bindings() -> Vec<wgpu::BindingType> {
+    buffer_bindings(wgpu::BufferBindingType::Uniform, &[ (mem::size_of::<f32>() * Settings::default().uniform_data().len()) as u32 ])
+}
+
+/// A texture read through a filtering sampler
+fn source_bindings() -> Vec<wgpu::BindingType> {
+    vec![texture_binding(), wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)]
+}
+
+fn texture_binding() -> wgpu::BindingType {
+    wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        view_dimension: wgpu::TextureViewDimension::D2,
+        multisampled: false,
+    }
+}
+
+/// The bloom passes and composite of `PostProcess`, and `Blit`, with their bind
+/// group layouts. For tests/shaders.rs
+#[doc(hidden)]
+pub fn pipeline_bindings() -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("post.wgsl", &["main_vs", "threshold_fs", "downsample_fs", "upsample_fs"], vec![constants_bindings(), source_bindings()]),
+        PipelineBindings::new("post.wgsl", &["main_vs", "composite_fs"], vec![constants_bindings(), source_bindings(), vec![texture_binding()]]),
+        PipelineBindings::new("blit.wgsl", &["main_vs", "main_fs"], vec![source_bindings()]),
+    ]
+}
+
+fn make_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, entry_point: &str, target: wgpu::ColorTargetState) -> wgpu::RenderPipeline {
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "main_vs",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[target],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn draw(encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, load: wgpu::LoadOp<wgpu::Color>, pipeline: &wgpu::RenderPipeline, bind_groups: &[&wgpu::BindGroup]) {
+
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: None,
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations { load, store: true },
+        }],
+        depth_stencil_attachment: None,
+    });
+    rpass.set_pipeline(pipeline);
+    for (i, bind_group) in bind_groups.iter().enumerate() {
+        rpass.set_bind_group(i as u32, bind_group, &[]);
+    }
+    rpass.draw(0..3, 0..1);
+}
+
+impl PostProcess {
+
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat, width: u32, height: u32, settings: Settings) -> Self {
+
+        let shader = make_shader(device, include_str!("post.wgsl"));
+
+        let constants_buffer = make_buffer(device, &settings.uniform_data());
+        let constants_layout = make_layout(device, wgpu::ShaderStages::FRAGMENT, &constants_bindings());
+        let constants_bind_group = make_bind_group(device, &constants_layout, &[&constants_buffer]);
+
+        let source_layout = make_layout(device, wgpu::ShaderStages::FRAGMENT, &source_bindings());
+        let bloom_layout = make_layout(device, wgpu::ShaderStages::FRAGMENT, &[ texture_binding() ]);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pass_layout = make_pipeline_layout(device, &[ &constants_layout, &source_layout ]);
+        let composite_layout = make_pipeline_layout(device, &[ &constants_layout, &source_layout, &bloom_layout ]);
+
+        let replace = wgpu::ColorTargetState::from(HDR_FORMAT);
+        let additive = wgpu::ColorTargetState {
+            format: HDR_FORMAT,
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+
+        let threshold_pipeline = make_pipeline(device, &pass_layout, &shader, "threshold_fs", replace.clone());
+        let downsample_pipeline = make_pipeline(device, &pass_layout, &shader, "downsample_fs", replace);
+        let upsample_pipeline = make_pipeline(device, &pass_layout, &shader, "upsample_fs", additive);
+        let composite_pipeline = make_pipeline(device, &composite_layout, &shader, "composite_fs", output_format.into());
+
+        let (hdr, levels, composite_bind_group) = Self::make_targets(device, width, height, &source_layout, &bloom_layout, &sampler);
+
+        PostProcess {
+            settings,
+            dirty: false,
+            constants_buffer,
+            constants_bind_group,
+            source_layout,
+            bloom_layout,
+            sampler,
+            hdr,
+            levels,
+            composite_bind_group,
+            threshold_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn make_targets(device: &wgpu::Device, width: u32, height: u32, source_layout: &wgpu::BindGroupLayout, bloom_layout: &wgpu::BindGroupLayout, sampler: &wgpu::Sampler) -> (Target, Vec<Target>, wgpu::BindGroup) {
+
+        let hdr = make_target(device, source_layout, sampler, width, height);
+
+        // Each bloom level is half the size of the one before it, starting at half resolution
+        let levels = (1..=BLOOM_LEVELS)
+            .map(|i| make_target(device, source_layout, sampler, width >> i, height >> i))
+            .collect::<Vec<_>>();
+
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: bloom_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&levels[0].view) },
+            ],
+        });
+
+        (hdr, levels, composite_bind_group)
+    }
+
+    /// Recreates the HDR target and bloom chain to match the new output size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let (hdr, levels, composite_bind_group) = Self::make_targets(device, width, height, &self.source_layout, &self.bloom_layout, &self.sampler);
+        self.hdr = hdr;
+        self.levels = levels;
+        self.composite_bind_group = composite_bind_group;
+    }
+
+    /// View the scene should be rendered into before calling `run`
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.hdr.view
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.dirty = true;
+    }
+
+    /// Records the bloom chain and the tone mapped composite into `output`
+    pub fn run(&mut self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, output: &wgpu::TextureView) {
+
+        if self.dirty {
+            queue.write_buffer(&self.constants_buffer, 0, bytemuck::cast_slice(&self.settings.uniform_data()));
+            self.dirty = false;
+        }
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        encoder.push_debug_group("bloom");
+        draw(encoder, &self.levels[0].view, clear, &self.threshold_pipeline, &[&self.constants_bind_group, &self.hdr.source]);
+        for i in 1..self.levels.len() {
+            draw(encoder, &self.levels[i].view, clear, &self.downsample_pipeline, &[&self.constants_bind_group, &self.levels[i - 1].source]);
+        }
+        for i in (1..self.levels.len()).rev() {
+            draw(encoder, &self.levels[i - 1].view, wgpu::LoadOp::Load, &self.upsample_pipeline, &[&self.constants_bind_group, &self.levels[i].source]);
+        }
+        encoder.pop_debug_group();
+
+        encoder.push_debug_group("tone mapping");
+        draw(encoder, output, clear, &self.composite_pipeline, &[&self.constants_bind_group, &self.hdr.source, &self.composite_bind_group]);
+        encoder.pop_debug_group();
+    }
+}
+
+/// Draws a texture over another view of any size, so a frame rendered off
+/// screen can be shown without drawing it twice
+pub struct Blit {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Blit {
+
+    pub fn new(device: &wgpu::Device, source: &wgpu::TextureView, output_format: wgpu::TextureFormat) -> Self {
+
+        let shader = make_shader(device, include_str!("blit.wgsl"));
+
+        let layout = make_layout(device, wgpu::ShaderStages::FRAGMENT, &source_bindings());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(source) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&sampler) },
+            ],
+        });
+
+        let pipeline_layout = make_pipeline_layout(device, &[ &layout ]);
+        let pipeline = make_pipeline(device, &pipeline_layout, &shader, "main_fs", output_format.into());
+
+        Blit { pipeline, bind_group }
+    }
+
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        draw(encoder, output, wgpu::LoadOp::Clear(wgpu::Color::BLACK), &self.pipeline, &[&self.bind_group]);
+    }
+}
@@ -0,0 +1,104 @@
+struct VertexOutput {
+    @builtin(position) p: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct PostConstants {
+    exposure : f32,
+    bloomThreshold : f32,
+    bloomStrength : f32,
+    toneMapping : f32,
+};
+
+@group(0) @binding(0) var<uniform> post : PostConstants;
+@group(1) @binding(0) var source : texture_2d<f32>;
+@group(1) @binding(1) var source_sampler : sampler;
+@group(2) @binding(0) var bloom : texture_2d<f32>;
+
+// A single triangle covering the whole screen, no vertex buffer needed
+@vertex
+fn main_vs(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+
+    var output_val: VertexOutput;
+
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+
+    output_val.p = vec4<f32>(x, y, 0.0, 1.0);
+    output_val.uv = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return output_val;
+}
+
+fn texel () -> vec2<f32> {
+    let size = textureDimensions(source);
+    return vec2<f32>(1.0 / f32(size.x), 1.0 / f32(size.y));
+}
+
+// Keep only the part of each pixel brighter than the threshold
+@fragment
+fn threshold_fs(i: VertexOutput) -> @location(0) vec4<f32> {
+
+    let c = textureSample(source, source_sampler, i.uv).rgb;
+    let brightness = max(c.r, max(c.g, c.b));
+    let contribution = max(brightness - post.bloomThreshold, 0.0) / max(brightness, 0.0001);
+
+    return vec4<f32>(c * contribution, 1.0);
+}
+
+// Box filter four bilinear taps from the larger level
+@fragment
+fn downsample_fs(i: VertexOutput) -> @location(0) vec4<f32> {
+
+    let t = texel();
+    var c = textureSample(source, source_sampler, i.uv + vec2<f32>(-t.x, -t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(t.x, -t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(-t.x, t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(t.x, t.y)).rgb;
+
+    return vec4<f32>(c / 4.0, 1.0);
+}
+
+// Tent filter the smaller level, blended additively onto the larger one
+@fragment
+fn upsample_fs(i: VertexOutput) -> @location(0) vec4<f32> {
+
+    let t = texel();
+    var c = textureSample(source, source_sampler, i.uv).rgb * 4.0;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(-t.x, 0.0)).rgb * 2.0;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(t.x, 0.0)).rgb * 2.0;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(0.0, -t.y)).rgb * 2.0;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(0.0, t.y)).rgb * 2.0;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(-t.x, -t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(t.x, -t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(-t.x, t.y)).rgb;
+    c += textureSample(source, source_sampler, i.uv + vec2<f32>(t.x, t.y)).rgb;
+
+    return vec4<f32>(c / 16.0, 1.0);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces (c : vec3<f32>) -> vec3<f32> {
+    let a = c * (2.51 * c + 0.03);
+    let b = c * (2.43 * c + 0.59) + 0.14;
+    return clamp(a / b, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn tone_map (c : vec3<f32>) -> vec3<f32> {
+    if (post.toneMapping < 0.5) {
+        return clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
+    }
+    if (post.toneMapping < 1.5) {
+        return c / (c + 1.0);
+    }
+    return aces(c);
+}
+
+@fragment
+fn composite_fs(i: VertexOutput) -> @location(0) vec4<f32> {
+
+    let hdr = textureSample(source, source_sampler, i.uv).rgb;
+    let glow = textureSample(bloom, source_sampler, i.uv).rgb;
+    let c = (hdr + glow * post.bloomStrength) * post.exposure;
+
+    return vec4<f32>(tone_map(c), 1.0);
+}
@@ -0,0 +1,169 @@
+//! Python bindings, built with `maturin develop --release` from this directory.
+//!
+//! ```python
+//! import particles
+//! sim = particles.Simulation(num_particles=500_000, sense_force=0.3, spawn="ring:0.4,0.1", map_init="fbm", seed=7)
+//! sim.step(100)
+//! trail_map = sim.get_map()        # (map_fidelity, map_fidelity) float32
+//! agents = sim.get_particles()     # (num_particles, 7) float32
+//! sim.set_params(fade_power=0.02)
+//! sim.set_params(num_particles=800_000)   # grows up to max_particles
+//!
+//! eco = particles.Simulation(lifecycle=1, energy_decay=0.001)
+//! eco.step(500)
+//! eco.get_params()["num_particles"]        # births minus deaths so far
+//! energy = eco.get_energy()                # (num_particles,) float32
+//!
+//! run = particles.Simulation(deterministic=1, seed=43)
+//! run.step(1000)
+//! run.run_hash()                           # the same on every run on this backend
+//! ```
+
+use numpy::{PyArray1, PyArray2, PyArrayMethods};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::config::Config;
+use crate::particle::Particle;
+use crate::util::request_headless_device;
+
+fn apply(config: &mut Config, params: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+    if let Some(params) = params {
+        for (key, value) in params.iter() {
+            let name: String = key.extract()?;
+            match name.as_str() {
+                "spawn" => config.spawn.pattern = value.extract::<String>()?.parse().map_err(PyValueError::new_err)?,
+                "velocity" => config.spawn.velocity = value.extract::<String>()?.parse().map_err(PyValueError::new_err)?,
+                "species" => config.spawn.species = value.extract::<String>()?.parse().map_err(PyValueError::new_err)?,
+                "map_init" => config.map_init = value.extract::<String>()?.parse().map_err(PyValueError::new_err)?,
+                _ => config.set(&name, value.extract()?).map_err(PyValueError::new_err)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A simulation running on its own GPU device
+#[pyclass(unsendable, name = "Simulation", module = "particles")]
+struct PySimulation {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    simulation: crate::Simulation,
+}
+
+#[pymethods]
+impl PySimulation {
+
+    /// Creates a simulation, any keyword overrides the matching default in `Config`,
+    /// `spawn`, `velocity`, `species` and `map_init` take the same strings as the command line
+    #[new]
+    #[pyo3(signature = (**params))]
+    fn new(params: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut config = Config::default();
+        apply(&mut config, params)?;
+        config.validate().map_err(PyValueError::new_err)?;
+
+        let (device, queue) = request_headless_device().map_err(PyRuntimeError::new_err)?;
+        let simulation = crate::Simulation::new(&device, &queue, config).map_err(PyValueError::new_err)?;
+        Ok(PySimulation { device, queue, simulation })
+    }
+
+    /// Advances the simulation by `n` steps and waits for the GPU to finish
+    #[pyo3(signature = (n = 1))]
+    fn step(&mut self, n: u32) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for _ in 0..n {
+            self.simulation.step(&mut encoder);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+        self.simulation.sync_particle_count(&self.device, &self.queue);
+    }
+
+    /// Respawns the particles and clears the map, using the current spawn settings and `seed`
+    fn reset(&mut self) {
+        self.simulation.reset(&self.queue);
+    }
+
+    /// Spawns `count` particles flying out of `(x, y)`, returns how many fit under `max_particles`
+    #[pyo3(signature = (x, y, count = 1000))]
+    fn spawn_at(&mut self, x: f32, y: f32, count: u32) -> u32 {
+        self.simulation.spawn_at(&self.device, &self.queue, x, y, count)
+    }
+
+    /// The trail map as a `(map_fidelity, map_fidelity)` array, row 0 at the top
+    fn get_map<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let size = self.simulation.config().map_fidelity as usize;
+        let map = self.simulation.read_map(&self.device, &self.queue);
+        PyArray1::from_vec(py, map).reshape([size, size])
+    }
+
+    /// Live particles as an `(N, 7)` array of `[pos_x, pos_y, sense_distance, vel_x, vel_y, preference, energy]`
+    fn get_particles<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let particles = self.simulation.read_particles(&self.device, &self.queue);
+        let count = self.simulation.config().num_particles as usize;
+        PyArray1::from_vec(py, particles).reshape([count, Particle::FLOATS])
+    }
+
+    /// Energy of every live particle as an `(N,)` array, `None` unless created with `lifecycle=1`
+    fn get_energy<'py>(&mut self, py: Python<'py>) -> Option<Bound<'py, PyArray1<f32>>> {
+        if !self.simulation.config().lifecycle {
+            return None;
+        }
+        let particles = self.simulation.read_particles(&self.device, &self.queue);
+        let energy = particles.chunks(Particle::FLOATS).map(|particle| particle[Particle::FLOATS - 1]).collect();
+        Some(PyArray1::from_vec(py, energy))
+    }
+
+    /// Statistics of the map and particles reduced on the GPU, as a dict keyed like the
+    /// columns of `--metrics`
+    fn measure<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let m = self.simulation.measure(&self.device, &self.queue);
+        let values = PyDict::new(py);
+        values.set_item("step", m.step)?;
+        values.set_item("particles", m.particles)?;
+        values.set_item("trail_mass", m.trail_mass)?;
+        values.set_item("trail_mean", m.trail_mean)?;
+        values.set_item("trail_variance", m.trail_variance)?;
+        values.set_item("occupied", m.occupied)?;
+        values.set_item("mean_speed", m.mean_speed)?;
+        values.set_item("mixing", m.mixing)?;
+        Ok(values)
+    }
+
+    /// 64 bit hash of the map and particles, the same on every run of a seed and
+    /// config with `deterministic=1` on the same backend
+    fn run_hash(&mut self) -> u64 {
+        self.simulation.run_hash(&self.device, &self.queue)
+    }
+
+    /// Changes parameters between steps, `max_particles` and `map_fidelity` are fixed
+    #[pyo3(signature = (**params))]
+    fn set_params(&mut self, params: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+        let mut config = self.simulation.config().clone();
+        apply(&mut config, params)?;
+        self.simulation.set_config(&self.device, &self.queue, config).map_err(PyValueError::new_err)
+    }
+
+    /// Every parameter and its current value
+    fn get_params<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let params = PyDict::new(py);
+        for name in Config::PARAMS {
+            params.set_item(name, self.simulation.config().get(name))?;
+        }
+        Ok(params)
+    }
+
+    /// How many steps were run so far
+    #[getter]
+    fn steps(&self) -> u32 {
+        self.simulation.steps()
+    }
+}
+
+#[pymodule]
+fn particles(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySimulation>()?;
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroU32;
+use std::process::{Child, Command, Stdio};
+
+use particles::post::Blit;
+
+use crate::cli::{Record, RecordOutput};
+
+enum Sink {
+    Apng(png::Writer<BufWriter<File>>),
+    Pipe(Child),
+}
+
+/// Captures rendered frames from an offscreen target and streams them out.
+///
+/// Each frame is copied into a staging buffer alongside the rest of the
+/// frame's commands, then read back once the queue has been submitted.
+pub struct Recorder {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    staging: wgpu::Buffer,
+    // Puts the recorded frame on screen too
+    blit: Blit,
+
+    width: u32,
+    height: u32,
+    padded_row: u32,
+    // The swapchain is often BGRA, frames always leave as RGBA
+    swizzle: bool,
+
+    sink: Sink,
+    frames: u32,
+    written: u32,
+}
+
+fn open_sink(record: &Record, width: u32, height: u32) -> io::Result<Sink> {
+    match &record.output {
+        RecordOutput::Apng(path) => {
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = png::Encoder::new(file, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(record.frames, 0).map_err(io::Error::other)?;
+            encoder.set_frame_delay(1, record.fps as u16).map_err(io::Error::other)?;
+            Ok(Sink::Apng(encoder.write_header().map_err(io::Error::other)?))
+        }
+        RecordOutput::Pipe(command) => {
+            let command = command
+                .replace("{width}", &width.to_string())
+                .replace("{height}", &height.to_string())
+                .replace("{fps}", &record.fps.to_string());
+
+            #[cfg(windows)]
+            let mut shell = Command::new("cmd");
+            #[cfg(windows)]
+            shell.arg("/C");
+            #[cfg(not(windows))]
+            let mut shell = Command::new("sh");
+            #[cfg(not(windows))]
+            shell.arg("-c");
+
+            Ok(Sink::Pipe(shell.arg(command).stdin(Stdio::piped()).spawn()?))
+        }
+    }
+}
+
+impl Recorder {
+
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32, record: &Record) -> io::Result<Self> {
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let blit = Blit::new(device, &view, format);
+
+        // Rows of a texture copy have to be aligned to 256 bytes
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (width * 4).div_ceil(align) * align;
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let swizzle = matches!(format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb);
+
+        Ok(Recorder {
+            texture,
+            view,
+            staging,
+            blit,
+            width,
+            height,
+            padded_row,
+            swizzle,
+            sink: open_sink(record, width, height)?,
+            frames: record.frames,
+            written: 0,
+        })
+    }
+
+    /// View each recorded frame should be drawn into
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.written >= self.frames
+    }
+
+    /// Records the copy of the target into the staging buffer
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 },
+        );
+    }
+
+    /// Draws the recorded frame over `view`, stretched to its size
+    pub fn show(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.blit.run(encoder, view);
+    }
+
+    /// Reads back the frame copied by `copy`, must be called after the queue was submitted
+    pub fn write_frame(&mut self, device: &wgpu::Device) -> io::Result<()> {
+
+        let slice = self.staging.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).map_err(io::Error::other)?;
+
+        let mut frame = Vec::with_capacity((self.width * self.height * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_row as usize) {
+                frame.extend_from_slice(&row[..(self.width * 4) as usize]);
+            }
+        }
+        self.staging.unmap();
+
+        if self.swizzle {
+            for pixel in frame.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        match &mut self.sink {
+            Sink::Apng(writer) => writer.write_image_data(&frame).map_err(io::Error::other)?,
+            Sink::Pipe(child) => child.stdin.as_mut().expect("encoder stdin is piped").write_all(&frame)?,
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Finalises the file, or closes the pipe and waits for the encoder to exit
+    pub fn finish(self) -> io::Result<()> {
+        match self.sink {
+            Sink::Apng(writer) => writer.finish().map_err(io::Error::other),
+            Sink::Pipe(mut child) => {
+                drop(child.stdin.take());
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!("encoder exited with {}", status)))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! The simulation on the CPU, for tests and for checking the shaders.
+//!
+//! A straight port of `compute_map.wgsl` and `compute.wgsl` that runs one
+//! invocation at a time in index order. The GPU runs them side by side and
+//! races on the map, so this is one of the outcomes the GPU may produce rather
+//! than the outcome, but it is the same on every run and every machine.
+//! `lifecycle` is not modelled.
+
+use crate::config::Config;
+use crate::constants::SimulationConstants;
+use crate::particle::Particle;
+
+/// Particles and trail map stepped on the CPU, started like `Simulation::reset`
+pub struct Reference {
+    config: Config,
+    particles: Vec<Particle>,
+    map: Vec<f32>,
+    indicators: Vec<f32>,
+    steps: u32,
+}
+
+// compute.wgsl's own approximations of pi, kept so the results match
+#[allow(clippy::approx_constant)]
+const PI: f32 = 3.141592;
+#[allow(clippy::approx_constant)]
+const TAU: f32 = 6.283185;
+
+// PCG hash, as in compute.wgsl
+fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+// Uniform float in [0, 1)
+fn random(x: u32) -> f32 {
+    (hash(x) >> 8) as f32 / 16777216.0
+}
+
+/// The value the best of the three senses carries, see `choose_one` in compute.wgsl
+fn choose_one(a: [f32; 3], b: [f32; 3], c: [f32; 3], instability: f32) -> [f32; 3] {
+    let a_value = (instability - a[0]).abs();
+    let b_value = (instability - b[0]).abs();
+    let c_value = (instability - c[0]).abs();
+
+    if a_value < b_value && a_value < c_value {
+        a
+    } else if b_value < c_value {
+        b
+    } else {
+        c
+    }
+}
+
+impl Reference {
+
+    pub fn new(config: Config) -> Self {
+        let cells = (config.map_fidelity * config.map_fidelity) as usize;
+        Reference {
+            particles: config.spawn.generate(config.num_particles, config.max_speed, config.seed),
+            map: config.map_init.generate(config.map_fidelity, config.seed),
+            indicators: vec![0.0; cells],
+            config,
+            steps: 0,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Trail map, `map_fidelity` rows with row 0 at the top
+    pub fn map(&self) -> &[f32] {
+        &self.map
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Fades the map then moves every particle, as `Simulation::step`
+    pub fn step(&mut self) {
+        let consts = self.config.constants();
+        for index in 0..self.map.len() {
+            self.step_cell(&consts, index);
+        }
+        for index in 0..self.particles.len() {
+            self.step_particle(&consts, index);
+        }
+        self.steps += 1;
+    }
+
+    fn fidelity(&self) -> f32 {
+        self.config.map_fidelity as f32
+    }
+
+    fn get_cell_index(&self, x: f32, y: f32) -> i32 {
+        let fidelity = self.fidelity();
+
+        let world_x = (x + 1.0) / 2.0 * fidelity;
+        let world_y = (-y + 1.0) / 2.0 * fidelity;
+
+        let mut index_x = world_x.floor();
+        let mut index_y = world_y.floor();
+
+        if index_x < 0.0 { index_x += fidelity; }
+        if index_y < 0.0 { index_y += fidelity; }
+        if index_y > fidelity - 1.0 { index_y -= fidelity; }
+        if index_x > fidelity - 1.0 { index_x -= fidelity; }
+
+        (index_y * fidelity + index_x) as i32
+    }
+
+    // Out of range reads give 0 and writes are dropped, like robust buffer access
+    fn read(&self, index: i32) -> f32 {
+        usize::try_from(index).ok().and_then(|i| self.map.get(i)).copied().unwrap_or(0.0)
+    }
+
+    fn sense_at_angle(&mut self, x: f32, y: f32, rotation: f32, distance: f32) -> [f32; 3] {
+        let dx = rotation.cos();
+        let dy = rotation.sin();
+
+        let x_1 = x + dx * distance;
+        let y_1 = y + dy * distance;
+        let x_2 = x_1 + dx * distance;
+        let y_2 = y_1 + dy * distance;
+        let x_3 = x_2 + dx * distance;
+        let y_3 = y_2 + dy * distance;
+
+        let indexes = [self.get_cell_index(x_1, y_1), self.get_cell_index(x_2, y_2), self.get_cell_index(x_3, y_3)];
+        for i in indexes {
+            if let Some(indicator) = usize::try_from(i).ok().and_then(|i| self.indicators.get_mut(i)) {
+                *indicator = 1.0;
+            }
+        }
+
+        // The shader averages the second cell twice and never reads the third
+        let values = [self.read(indexes[0]), self.read(indexes[1]), self.read(indexes[1])];
+        let average = (values[0] + values[1] + values[2]) / 3.0;
+
+        [average, values[2] - values[0], rotation]
+    }
+
+    fn step_particle(&mut self, consts: &SimulationConstants, index: usize) {
+        let particle = self.particles[index];
+
+        let dist = particle.sense_distance;
+        let target = particle.preference;
+
+        let mut pos = [particle.pos_x, particle.pos_y];
+        let mut vel = [particle.vel_x, particle.vel_y];
+
+        // Death and respawn
+        let seed = hash(index as u32 ^ hash(pos[0].to_bits() ^ hash(pos[1].to_bits())));
+        let alive = pos[0].abs() <= 2.0 && pos[1].abs() <= 2.0 && vel[0].abs() <= 2.0 && vel[1].abs() <= 2.0;
+        if !alive || random(seed) < consts.respawn_chance {
+            let direction = random(seed.wrapping_add(3)) * TAU;
+            pos = [random(seed.wrapping_add(1)) * 2.0 - 1.0, random(seed.wrapping_add(2)) * 2.0 - 1.0];
+            vel = [direction.cos() * consts.max_speed, direction.sin() * consts.max_speed];
+        }
+
+        // Guard checking
+        for p in pos.iter_mut() {
+            if *p < -1.0 { *p += 2.0; }
+            if *p > 1.0 { *p -= 2.0; }
+        }
+
+        let speed = (vel[0] * vel[0] + vel[1] * vel[1]).sqrt();
+        if speed > consts.max_speed {
+            vel = vel.map(|v| v * (consts.max_speed / speed));
+        }
+        if speed < consts.min_speed {
+            vel = vel.map(|v| v * (consts.min_speed / speed));
+        }
+
+        // Flow on the grid
+        let angle = -vel[0].atan2(vel[1]) + PI / 2.0;
+        let offset = PI / 12.0;
+
+        let left = self.sense_at_angle(pos[0], pos[1], angle + offset, dist);
+        let center = self.sense_at_angle(pos[0], pos[1], angle, dist);
+        let right = self.sense_at_angle(pos[0], pos[1], angle - offset, dist);
+
+        let best_angle = choose_one(left, center, right, target);
+
+        let magnitude = (vel[0] * vel[0] + vel[1] * vel[1]).sqrt();
+        let sense_v = [best_angle[2].cos() * magnitude, best_angle[2].sin() * magnitude];
+
+        let pull = consts.sense_force;
+        let inv = 1.0 - pull;
+        vel = [inv * vel[0] + pull * sense_v[0], inv * vel[1] + pull * sense_v[1]];
+        vel = vel.map(|v| v * (1.0 + best_angle[1] / 10.0));
+
+        // Movement
+        pos = [pos[0] + vel[0], pos[1] + vel[1]];
+
+        self.particles[index] = Particle { pos_x: pos[0], pos_y: pos[1], vel_x: vel[0], vel_y: vel[1], ..particle };
+
+        // Update the map
+        let i = self.get_cell_index(pos[0], pos[1]);
+        if let Some(cell) = usize::try_from(i).ok().and_then(|i| self.map.get_mut(i)) {
+            *cell = (*cell + consts.cell_impact).min(consts.trail_ceiling);
+        }
+    }
+
+    fn who_cell(&self, x: i32, y: i32) -> usize {
+        let fidelity = self.config.map_fidelity as i32;
+        (y.rem_euclid(fidelity) * fidelity + x.rem_euclid(fidelity)) as usize
+    }
+
+    fn step_cell(&mut self, consts: &SimulationConstants, index: usize) {
+        let fidelity = self.config.map_fidelity as usize;
+        let cell_x = (index % fidelity) as i32;
+        let cell_y = (index / fidelity) as i32;
+
+        self.map[index] *= consts.erase_power;
+        self.indicators[index] = 0.0;
+
+        let neighbours = [
+            self.who_cell(cell_x - 1, cell_y),
+            self.who_cell(cell_x + 1, cell_y),
+            self.who_cell(cell_x, cell_y - 1),
+            self.who_cell(cell_x, cell_y + 1),
+        ];
+        let mut taken = 0.0;
+        for n in neighbours {
+            let take = self.map[n] * consts.fade_power;
+            self.map[n] -= take;
+            taken += take;
+        }
+
+        let mut value = self.map[index] + taken * 0.6;
+        if value > consts.trail_ceiling {
+            value = consts.trail_ceiling;
+        }
+        if value < 0.00001 {
+            value = 0.0;
+        }
+        self.map[index] = value;
+    }
+}
+
+/// Linear to sRGB, as an `Srgb` render target encodes on write
+fn to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let encoded = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (encoded * 255.0).round() as u8
+}
+
+/// RGB8 image of a trail map with the colour ramp of `render_map.wgsl` on an sRGB
+/// target, one pixel per cell and row 0 at the top. The app draws row 0 at the
+/// bottom, so the image is the app's view upside down
+pub fn colour_map(map: &[f32]) -> Vec<u8> {
+    map.iter().flat_map(|&strength| {
+        let v = strength.min(1.0);
+        let (mut c1, mut c2, mut c3) = (v, 0.0, 0.0);
+        if v > 0.33 && v < 0.66 {
+            c1 = 0.33;
+            c2 = v - 0.33;
+        }
+        if v > 0.66 {
+            c2 = 0.33;
+            c3 = v - 0.66;
+            c1 = 0.33 - c3;
+        }
+        [to_srgb(c2), to_srgb(c3), to_srgb(c1)]
+    }).collect()
+}
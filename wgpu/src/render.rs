@@ -0,0 +1,175 @@
+use std::mem;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::simulation::constants_bindings;
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_layout, make_pipeline_layout, make_render_pipeline, make_shader, reload_shader, shader, PipelineBindings};
+
+/// Pipeline and buffers used to draw the map of a simulation into a texture view
+pub(crate) struct Renderer {
+
+    const_bind_vertex_group: wgpu::BindGroup,
+
+    view_buffer: wgpu::Buffer,
+    view_bind_group: wgpu::BindGroup,
+
+    square_vertex_buffer: wgpu::Buffer,
+
+    // Kept to rebuild the pipeline when the shader changes
+    target_format: wgpu::TextureFormat,
+    pipeline_layout_render_map: wgpu::PipelineLayout,
+
+    pipeline_render_map: wgpu::RenderPipeline,
+}
+
+/// Data laid out to match `ViewTransform` in the render shaders
+fn view_data(offset: [f32; 2], scale: f32) -> [f32; 4] {
+    [offset[0], offset[1], scale, 0.0]
+}
+
+/// The `ViewTransform` uniform
+fn view_bindings() -> Vec<wgpu::BindingType> {
+    let _f = mem::size_of::<f32>() as u32;
+    buffer_bindings(wgpu::BufferBindingType::Uniform, &[ _f * (view_data([0.0, 0.0], 1.0).len() as u32) ])
+}
+
+/// The map pipeline built in `Renderer::new`
+pub(crate) fn pipeline_bindings() -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("render_map.wgsl", &["main_vs", "main_fs"], vec![constants_bindings(), view_bindings()]),
+    ]
+}
+
+/// Instanced squares, one per map cell
+fn make_cell_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule) -> wgpu::RenderPipeline {
+    make_render_pipeline(device, format, layout, shader, &[
+        wgpu::VertexBufferLayout {
+            array_stride: 4,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &wgpu::vertex_attr_array![0 => Float32],
+        },
+        wgpu::VertexBufferLayout {
+            array_stride: 3 * 4,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![1 => Float32x3],
+        },
+    ])
+}
+
+impl Renderer {
+
+    pub fn new(device: &wgpu::Device, config: &Config, constant_data_buffer: &wgpu::Buffer) -> Self {
+
+        // Create the shader
+        let render_map_shader = make_shader(device, shader!("render_map.wgsl"));
+
+        let square_vertex_data = [
+            -0.5f32 ,   0.5     ,   0.0,
+            0.5     ,   0.5     ,   0.0,
+            0.5     ,   -0.5    ,   0.0,
+            -0.5    ,   0.5     ,   0.0,
+            0.5     ,   -0.5    ,   0.0,
+            -0.5    ,   -0.5    ,   0.0];
+        let square_vertex_buffer = make_buffer(device, &square_vertex_data);
+
+        let view_buffer = make_buffer(device, &view_data([0.0, 0.0], 1.0));
+
+        // Setup Bind Layouts
+
+        let binding_constants_vertex = make_layout(device, wgpu::ShaderStages::VERTEX, &constants_bindings());
+        let binding_view_vertex = make_layout(device, wgpu::ShaderStages::VERTEX, &view_bindings());
+
+
+        // Create the pipeline layout
+
+        let pipeline_layout_render_map = make_pipeline_layout(device, &[
+            &binding_constants_vertex,
+            &binding_view_vertex
+        ]);
+
+
+        // create the pipeline
+
+        let pipeline_render_map = make_cell_pipeline(device, config.target_format, &pipeline_layout_render_map, &render_map_shader);
+
+
+        // Constants Data
+
+        let const_bind_vertex_group = make_bind_group(device, &binding_constants_vertex, &[constant_data_buffer]);
+
+
+        // View Data
+
+        let view_bind_group = make_bind_group(device, &binding_view_vertex, &[&view_buffer]);
+
+        Renderer {
+
+            const_bind_vertex_group,
+
+            view_buffer,
+            view_bind_group,
+
+            square_vertex_buffer,
+
+            target_format: config.target_format,
+
+            pipeline_layout_render_map,
+
+            pipeline_render_map,
+        }
+    }
+
+    /// Rebuilds the pipeline from `render_map.wgsl` in `dir`, keeping the previous
+    /// version and returning its errors if it fails to build
+    pub fn reload_shaders(&mut self, device: &wgpu::Device, dir: &Path) -> Vec<String> {
+
+        let mut errors = Vec::new();
+        let format = self.target_format;
+
+        match reload_shader(device, dir, "render_map.wgsl", |shader| make_cell_pipeline(device, format, &self.pipeline_layout_render_map, shader)) {
+            Ok(pipeline) => self.pipeline_render_map = pipeline,
+            Err(e) => errors.push(e),
+        }
+
+        errors
+    }
+
+    pub fn set_view(&self, queue: &wgpu::Queue, offset: [f32; 2], scale: f32) {
+        queue.write_buffer(&self.view_buffer, 0, bytemuck::cast_slice(&view_data(offset, scale)));
+    }
+
+    /// Records the map into view, without advancing the simulation. The view is
+    /// cleared first so zooming out does not leave stale pixels around the map
+    pub fn render(&self, command_encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, config: &Config, map: &wgpu::Buffer) {
+
+        let cells = config.map_fidelity * config.map_fidelity;
+
+        // create render pass descriptor and its color attachments
+        let color_attachments = [wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        }];
+        let render_pass_descriptor = wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: None,
+        };
+
+        command_encoder.push_debug_group("render map");
+        {
+            // render pass map
+            let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
+            rpass.set_pipeline(&self.pipeline_render_map);
+            rpass.set_bind_group(0, &self.const_bind_vertex_group, &[]);
+            rpass.set_bind_group(1, &self.view_bind_group, &[]);
+            rpass.set_vertex_buffer(0, map.slice(..));
+            rpass.set_vertex_buffer(1, self.square_vertex_buffer.slice(..));
+            rpass.draw(0..6, 0..cells);
+        }
+        command_encoder.pop_debug_group();
+    }
+}
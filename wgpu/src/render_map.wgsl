@@ -1,23 +1,10 @@
 struct VertexOutput {
-    @builtin(position) p: vec4<f32>
-};
-
-struct SimulationConstants {
-    simulationSpeed : f32,
-    mapBounds : f32,
-    oobForce : f32,
-    maxSpeed : f32,
-    minSpeed : f32,
-    mapFidelity : f32,
-    cellImpact : f32,
-    senseDistance : f32,
-    senseForce : f32,
-    fadePower : f32,
-    erasePower : f32,
-    instabilityScore : f32,
+    @builtin(position) p: vec4<f32>,
+    @location(0) strength: f32,
 };
 
 @group(0) @binding(0) var<uniform> consts : SimulationConstants;
+@group(1) @binding(0) var<uniform> view : ViewTransform;
 
 @vertex
 fn main_vs(
@@ -42,8 +29,11 @@ fn main_vs(
 
     let v_x = cell_center_x + (position.x * vertex_scale * cell_scale);
     let v_y = cell_center_y + (position.y * vertex_scale * cell_scale);
+    let screen = (vec2<f32>(v_x, v_y) + view.offset) * view.scale;
         
-    final_value.p = vec4<f32>(vec3<f32>(v_x, v_y, strength ), 1.0);
+    // Strength travels separately, trails above 1.0 would otherwise be clipped as depth
+    final_value.p = vec4<f32>(vec3<f32>(screen.x, screen.y, min(strength, 1.0) ), 1.0);
+    final_value.strength = strength;
 
     return final_value;
 }
@@ -51,7 +41,7 @@ fn main_vs(
 @fragment
 fn main_fs(i: VertexOutput) -> @location(0) vec4<f32> {
 
-    var v = i.p.z;
+    var v = min(i.strength, 1.0);
     var c1 = v;
     var c2 = 0.0;
     var c3 = 0.0;
@@ -66,6 +56,9 @@ fn main_fs(i: VertexOutput) -> @location(0) vec4<f32> {
         c3 = v - 0.66;
         c1 = 0.33 - c3;
     }
-    return vec4<f32>(c2,c3,c1, 1.0);
+
+    // Above 1.0 the colour keeps its hue and only gets brighter, for the HDR target
+    let intensity = max(i.strength, 1.0);
+    return vec4<f32>(vec3<f32>(c2,c3,c1) * intensity, 1.0);
 
 }
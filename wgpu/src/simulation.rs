@@ -0,0 +1,504 @@
+use std::mem;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::constants::SimulationConstants;
+use crate::deterministic::{self, Deterministic};
+use crate::export;
+use crate::lifecycle::{self, Lifecycle};
+use crate::metrics::{self, Measurement, Metrics};
+use crate::particle::Particle;
+use crate::render::{self, Renderer};
+use crate::spawn::{Pattern, Spawn, Velocity};
+use crate::util::{buffer_bindings, make_bind_group, make_buffer, make_compute_pipeline, make_layout, make_pipeline_layout, make_shader, reload_shader, shader, dispatch_grid, PipelineBindings};
+
+/// The constants uniform, group 0 of every kernel and of the map's render pipeline
+pub(crate) fn constants_bindings() -> Vec<wgpu::BindingType> {
+    buffer_bindings(wgpu::BufferBindingType::Uniform, &[ SimulationConstants::SIZE as u32 ])
+}
+
+/// A particle buffer holding `max_particles`
+pub(crate) fn particle_bindings(config: &Config) -> Vec<wgpu::BindingType> {
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: false }, &[ (Particle::SIZE as u32) * config.max_particles ])
+}
+
+/// One f32 per map cell, for the map itself and the indicators
+pub(crate) fn map_bindings(config: &Config) -> Vec<wgpu::BindingType> {
+    let _f = mem::size_of::<f32>() as u32;
+    buffer_bindings(wgpu::BufferBindingType::Storage { read_only: false }, &[ _f * config.map_fidelity * config.map_fidelity ])
+}
+
+/// The particle and map kernels built in `Simulation::new`
+fn compute_bindings(config: &Config) -> Vec<PipelineBindings> {
+    vec![
+        PipelineBindings::new("compute.wgsl", &["main"], vec![constants_bindings(), particle_bindings(config), map_bindings(config), map_bindings(config)]),
+        PipelineBindings::new("compute_map.wgsl", &["main"], vec![constants_bindings(), map_bindings(config), map_bindings(config)]),
+    ]
+}
+
+/// Particles, the trail map, the two kernels that advance them and the
+/// pipelines that draw them, all on a device owned by the caller.
+///
+/// Nothing is submitted here: `step` and `render` only record into the
+/// caller's encoder, so the simulation can be one pass among many.
+pub struct Simulation {
+
+    config: Config,
+
+    constant_data_buffer: wgpu::Buffer,
+    const_bind_compute_group: wgpu::BindGroup,
+
+    raw_particle_buffer: wgpu::Buffer,
+    particle_bind_group: wgpu::BindGroup,
+
+    raw_indicator_buffer: wgpu::Buffer,
+    indicator_bind_group_compute: wgpu::BindGroup,
+
+    raw_map_buffer : wgpu::Buffer,
+    map_bind_group: wgpu::BindGroup,
+
+    // Kept to rebuild the pipelines when the shaders change
+    pipeline_layout_compute: wgpu::PipelineLayout,
+    pipeline_layout_compute_map: wgpu::PipelineLayout,
+
+    compute_map_pipeline: wgpu::ComputePipeline,
+    compute_pipeline: wgpu::ComputePipeline,
+
+    renderer: Renderer,
+
+    // Energy, death and reproduction, only when `Config::lifecycle` is set
+    lifecycle: Option<Lifecycle>,
+
+    // Race free kernels replacing the two above, only when `Config::deterministic` is set
+    deterministic: Option<Deterministic>,
+
+    // Reductions for `measure`, built on first use
+    metrics: Option<Metrics>,
+
+    // Kept for the kernels built after creation
+    binding_constants_compute: wgpu::BindGroupLayout,
+    binding_particles_compute: wgpu::BindGroupLayout,
+    binding_map_compute: wgpu::BindGroupLayout,
+
+    map_workgroups: (u32, u32),
+    // Workgroups a dispatch can have along one side on this device
+    max_workgroups: u32,
+
+    steps: u32,
+
+    // What `Config::fit` lowered for this device
+    fitted: Vec<String>,
+}
+
+impl Simulation {
+
+    /// Builds the buffers and pipelines for `config`, or says why it can't run
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, mut config: Config) -> Result<Self, String> {
+
+        config.validate()?;
+
+        // The capacity is fixed from here on, no more than the device can bind
+        config.max_particles = config.capacity();
+        // Reported by the caller through `fitted`
+        let fitted = config.fit(&device.limits());
+        let max_workgroups = device.limits().max_compute_workgroups_per_dimension;
+
+        // Create the shaders
+        let compute_particles_shader = make_shader(device, shader!("compute.wgsl"));
+        let compute_map_shader = make_shader(device, shader!("compute_map.wgsl"));
+
+        let cells = config.map_fidelity * config.map_fidelity;
+
+        // Construct constants to be bound to shaders
+        let constant_data_buffer = make_buffer(device, bytemuck::cast_slice(&[config.constants()]));
+
+        // Setup Bind Layouts
+
+        let binding_constants_compute = make_layout(device, wgpu::ShaderStages::COMPUTE, &constants_bindings());
+        let binding_particles_compute = make_layout(device, wgpu::ShaderStages::COMPUTE, &particle_bindings(&config));
+        let binding_map_compute = make_layout(device, wgpu::ShaderStages::COMPUTE, &map_bindings(&config));
+
+
+        // Create the pipeline layouts
+
+        let pipeline_layout_compute = make_pipeline_layout(device, &[
+            &binding_constants_compute,
+            &binding_particles_compute,
+            &binding_map_compute,
+            &binding_map_compute,
+        ]);
+
+        let pipeline_layout_compute_map = make_pipeline_layout(device, &[
+            &binding_constants_compute,
+            &binding_map_compute,
+            &binding_map_compute
+        ]);
+
+        let compute_pipeline = make_compute_pipeline(device, &pipeline_layout_compute, &compute_particles_shader);
+        let compute_map_pipeline = make_compute_pipeline(device, &pipeline_layout_compute_map, &compute_map_shader);
+
+
+        // Particles, filled in by `reset` below
+
+        let raw_particle_buffer = make_buffer(device, &vec![0.0f32; config.max_particles as usize * Particle::FLOATS]);
+        let particle_bind_group = make_bind_group(device, &binding_particles_compute, &[&raw_particle_buffer]);
+
+
+        // Map Data
+
+        let raw_map_buffer = make_buffer(device, &vec![0.0f32; cells as usize]);
+        let map_bind_group = make_bind_group(device, &binding_map_compute, &[&raw_map_buffer]);
+
+        let raw_indicator_map_data = vec![0.0f32; cells as usize];
+        let raw_indicator_buffer = make_buffer(device, &raw_indicator_map_data);
+        let indicator_bind_group_compute = make_bind_group(device, &binding_map_compute, &[&raw_indicator_buffer]);
+
+
+        // Constants Data
+
+        let const_bind_compute_group = make_bind_group(device, &binding_constants_compute, &[&constant_data_buffer]);
+
+
+        // Drawing, sharing the constants with the compute side
+
+        let renderer = Renderer::new(device, &config, &constant_data_buffer);
+
+
+        // Agents that eat, starve and divide
+
+        let lifecycle = if config.lifecycle {
+            Some(Lifecycle::new(device, &config, &binding_constants_compute, &binding_particles_compute, &binding_map_compute, &raw_particle_buffer))
+        } else {
+            None
+        };
+
+        let deterministic = if config.deterministic {
+            Some(Deterministic::new(device, &config, &binding_constants_compute, &binding_particles_compute, &binding_map_compute, &raw_indicator_buffer))
+        } else {
+            None
+        };
+
+        let mut simulation = Simulation {
+
+            map_workgroups: dispatch_grid(cells, max_workgroups),
+            max_workgroups,
+
+            config,
+
+            constant_data_buffer,
+            const_bind_compute_group,
+
+            raw_particle_buffer,
+            particle_bind_group,
+
+            raw_indicator_buffer,
+            indicator_bind_group_compute,
+
+            raw_map_buffer,
+            map_bind_group,
+
+            pipeline_layout_compute,
+            pipeline_layout_compute_map,
+            compute_map_pipeline,
+            compute_pipeline,
+
+            renderer,
+
+            lifecycle,
+
+            deterministic,
+
+            metrics: None,
+
+            binding_constants_compute,
+            binding_particles_compute,
+            binding_map_compute,
+
+            steps: 0,
+
+            fitted,
+        };
+        simulation.reset(queue);
+        Ok(simulation)
+    }
+
+    /// Puts the particles back at their starting positions and the map back to its initial state
+    pub fn reset(&mut self, queue: &wgpu::Queue) {
+        let cells = (self.config.map_fidelity * self.config.map_fidelity) as usize;
+        self.write_particles(queue, 0, &self.config.spawn.generate(self.config.num_particles, self.config.max_speed, self.config.seed));
+        self.write_constants(queue);
+        queue.write_buffer(&self.raw_map_buffer, 0, bytemuck::cast_slice(&self.config.map_init.generate(self.config.map_fidelity, self.config.seed)));
+        queue.write_buffer(&self.raw_indicator_buffer, 0, bytemuck::cast_slice(&vec![0.0f32; cells]));
+        self.steps = 0;
+    }
+
+    /// Uploads the constants, and the population the lifecycle kernel starts from
+    fn write_constants(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.constant_data_buffer, 0, bytemuck::bytes_of(&self.config.constants()));
+        if let Some(lifecycle) = &self.lifecycle {
+            lifecycle.write_count(queue, self.config.num_particles);
+        }
+    }
+
+    /// Writes particles into the live buffer from slot `first` on, with
+    /// `lifecycle` they start with half the energy needed to divide
+    fn write_particles(&self, queue: &wgpu::Queue, first: u32, particles: &[Particle]) {
+        let offset = self.particle_offset(first);
+        if self.lifecycle.is_some() {
+            let energy = self.config.reproduce_energy * 0.5;
+            let particles = particles.iter().map(|particle| Particle { energy, ..*particle }).collect::<Vec<_>>();
+            queue.write_buffer(self.particle_buffer(), offset, bytemuck::cast_slice(&particles));
+        } else {
+            queue.write_buffer(self.particle_buffer(), offset, bytemuck::cast_slice(particles));
+        }
+    }
+
+    /// Records a single simulation step, fading the map, moving every particle
+    /// and, with `lifecycle`, feeding, killing and dividing them
+    pub fn step(&mut self, command_encoder: &mut wgpu::CommandEncoder) {
+
+        // The population changes on the GPU with lifecycle, so cover every slot
+        let particle_workgroups = match self.lifecycle {
+            Some(_) => dispatch_grid(self.config.max_particles, self.max_workgroups),
+            None => dispatch_grid(self.config.num_particles, self.max_workgroups),
+        };
+        let particle_bind_group = self.particle_bind_group();
+
+        if let Some(deterministic) = &self.deterministic {
+            deterministic.step(command_encoder, &self.const_bind_compute_group, particle_bind_group, &self.map_bind_group, &self.raw_map_buffer, particle_workgroups);
+            self.steps += 1;
+            return;
+        }
+
+        command_encoder.push_debug_group("compute map changes");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.compute_map_pipeline);
+            cpass.set_bind_group(0, &self.const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, &self.map_bind_group, &[]);
+            cpass.set_bind_group(2, &self.indicator_bind_group_compute, &[]);
+            cpass.dispatch(self.map_workgroups.0, self.map_workgroups.1, 1);
+        }
+        command_encoder.pop_debug_group();
+
+        command_encoder.push_debug_group("compute boid movement");
+        {
+            // compute pass
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(&self.compute_pipeline);
+            cpass.set_bind_group(0, &self.const_bind_compute_group, &[]);
+            cpass.set_bind_group(1, particle_bind_group, &[]);
+            cpass.set_bind_group(2, &self.map_bind_group, &[]);
+            cpass.set_bind_group(3, &self.indicator_bind_group_compute, &[]);
+            cpass.dispatch(particle_workgroups.0, particle_workgroups.1, 1);
+        }
+        command_encoder.pop_debug_group();
+
+        if let Some(lifecycle) = self.lifecycle.as_mut() {
+            lifecycle.step(command_encoder, &self.const_bind_compute_group, &self.map_bind_group, &self.constant_data_buffer, particle_workgroups);
+        }
+
+        self.steps += 1;
+    }
+
+    /// Bind group of the particle buffer holding the live agents
+    fn particle_bind_group(&self) -> &wgpu::BindGroup {
+        match &self.lifecycle {
+            Some(lifecycle) if lifecycle.current() == 1 => lifecycle.particle_bind_group(),
+            _ => &self.particle_bind_group,
+        }
+    }
+
+    /// Reduces the map and particles on the GPU into a row of statistics,
+    /// submitting and waiting for the work. Call it between frames, not while
+    /// a step is recorded but not yet submitted
+    pub fn measure(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Measurement {
+        if self.metrics.is_none() {
+            self.metrics = Some(Metrics::new(device, &self.config, &self.binding_constants_compute, &self.binding_particles_compute, &self.binding_map_compute));
+        }
+        let metrics = self.metrics.as_ref().unwrap();
+        let totals = metrics.measure(device, queue, &self.const_bind_compute_group, self.particle_bind_group(), &self.map_bind_group);
+        Measurement::from_totals(self.steps, self.config.map_fidelity * self.config.map_fidelity, totals)
+    }
+
+    /// Records the map into view without advancing the simulation, view must
+    /// have the `target_format` the simulation was created with
+    pub fn render(&self, view: &wgpu::TextureView, command_encoder: &mut wgpu::CommandEncoder) {
+        self.renderer.render(command_encoder, view, &self.config, &self.raw_map_buffer);
+    }
+
+    /// Pan and zoom used by `render`, a point `p` is drawn at `(p + offset) * scale`
+    pub fn set_view(&self, queue: &wgpu::Queue, offset: [f32; 2], scale: f32) {
+        self.renderer.set_view(queue, offset, scale);
+    }
+
+    /// Rebuilds every pipeline from the `.wgsl` files in `dir` rather than the copies
+    /// built into the binary. A shader that fails to compile keeps its previous
+    /// pipeline and its validation errors are returned instead of panicking
+    pub fn reload_shaders(&mut self, device: &wgpu::Device, dir: &Path) -> Result<(), String> {
+
+        let mut errors = Vec::new();
+
+        match reload_shader(device, dir, "compute.wgsl", |shader| make_compute_pipeline(device, &self.pipeline_layout_compute, shader)) {
+            Ok(pipeline) => self.compute_pipeline = pipeline,
+            Err(e) => errors.push(e),
+        }
+        match reload_shader(device, dir, "compute_map.wgsl", |shader| make_compute_pipeline(device, &self.pipeline_layout_compute_map, shader)) {
+            Ok(pipeline) => self.compute_map_pipeline = pipeline,
+            Err(e) => errors.push(e),
+        }
+        if let Some(Err(e)) = self.lifecycle.as_mut().map(|lifecycle| lifecycle.reload_shaders(device, dir)) {
+            errors.push(e);
+        }
+        if let Some(deterministic) = self.deterministic.as_mut() {
+            errors.extend(deterministic.reload_shaders(device, dir));
+        }
+        errors.extend(self.renderer.reload_shaders(device, dir));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Uploads new parameters, the buffer sizes and target format set at creation can't change.
+    /// Growing `num_particles` spawns the new particles with the current `spawn`. With
+    /// `lifecycle` the population is read back first, waiting for the GPU
+    pub fn set_config(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, config: Config) -> Result<(), String> {
+        if config.max_particles != self.config.max_particles || config.map_fidelity != self.config.map_fidelity {
+            return Err("max_particles and map_fidelity can't change after the simulation was created".to_string());
+        }
+        if config.lifecycle != self.config.lifecycle {
+            return Err("lifecycle can't change after the simulation was created".to_string());
+        }
+        if config.deterministic != self.config.deterministic {
+            return Err("deterministic can't change after the simulation was created".to_string());
+        }
+        if config.num_particles > config.max_particles {
+            return Err(format!("num_particles can't exceed max_particles ({})", config.max_particles));
+        }
+        if config.target_format != self.config.target_format {
+            return Err("target_format can't change after the simulation was created".to_string());
+        }
+        self.sync_particle_count(device, queue);
+        if config.num_particles > self.config.num_particles {
+            let added = config.num_particles - self.config.num_particles;
+            let seed = config.seed.wrapping_add(self.config.num_particles as u64);
+            let data = config.spawn.generate(added, config.max_speed, seed);
+            let first = self.config.num_particles;
+            self.config = config.clone();
+            self.write_particles(queue, first, &data);
+        }
+        self.config = config;
+        self.write_constants(queue);
+        Ok(())
+    }
+
+    /// With `lifecycle` the population changes on the GPU, this reads it back
+    /// into `config().num_particles` and returns it. Waits for the GPU
+    pub fn sync_particle_count(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        if let Some(lifecycle) = &self.lifecycle {
+            self.config.num_particles = lifecycle.read_count(device, queue);
+        }
+        self.config.num_particles
+    }
+
+    fn particle_offset(&self, index: u32) -> wgpu::BufferAddress {
+        (index as usize * Particle::SIZE) as wgpu::BufferAddress
+    }
+
+    /// Appends particles after the live ones. Returns how many fit, anything
+    /// beyond `max_particles` is dropped
+    pub fn add_particles(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, particles: &[Particle]) -> u32 {
+        self.sync_particle_count(device, queue);
+        let free = self.config.max_particles - self.config.num_particles;
+        let added = (particles.len() as u32).min(free);
+        if added > 0 {
+            self.write_particles(queue, self.config.num_particles, &particles[..added as usize]);
+            self.config.num_particles += added;
+            self.write_constants(queue);
+        }
+        added
+    }
+
+    /// Spawns a burst of `count` particles flying out of `(x, y)`, returns how many fit
+    pub fn spawn_at(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, x: f32, y: f32, count: u32) -> u32 {
+        self.sync_particle_count(device, queue);
+        let burst = Spawn {
+            pattern: Pattern::Point { x, y },
+            velocity: Velocity::Outward,
+            species: self.config.spawn.species,
+        };
+        let seed = self.config.seed.wrapping_add(self.config.num_particles as u64).wrapping_add(self.steps as u64);
+        let data = burst.generate(count.min(self.config.max_particles - self.config.num_particles), self.config.max_speed, seed);
+        self.add_particles(device, queue, &data)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// What the config was lowered by to fit the device, a line for each change
+    pub fn fitted(&self) -> &[String] {
+        &self.fitted
+    }
+
+    /// How many steps were recorded so far
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Uniform holding `SimulationConstants`, shared with the render shaders
+    pub fn constants_buffer(&self) -> &wgpu::Buffer {
+        &self.constant_data_buffer
+    }
+
+    /// A `Particle` for each of `max_particles`, the first `num_particles` are alive
+    pub fn particle_buffer(&self) -> &wgpu::Buffer {
+        match &self.lifecycle {
+            Some(lifecycle) if lifecycle.current() == 1 => lifecycle.particle_buffer(),
+            _ => &self.raw_particle_buffer,
+        }
+    }
+
+    /// One f32 per cell, `map_fidelity` rows of `map_fidelity` cells
+    pub fn map_buffer(&self) -> &wgpu::Buffer {
+        &self.raw_map_buffer
+    }
+
+    /// Cells sensed during the last step, same layout as the map
+    pub fn indicator_buffer(&self) -> &wgpu::Buffer {
+        &self.raw_indicator_buffer
+    }
+
+    pub fn read_map(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<f32> {
+        export::read_buffer(device, queue, &self.raw_map_buffer, (self.config.map_fidelity * self.config.map_fidelity) as usize)
+    }
+
+    /// The live particles, flattened to `Particle::FLOATS` f32 each as exported
+    pub fn read_particles(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<f32> {
+        self.sync_particle_count(device, queue);
+        export::read_buffer(device, queue, self.particle_buffer(), self.config.num_particles as usize * Particle::FLOATS)
+    }
+
+    /// 64 bit FNV-1a hash of the map and live particles, waits for the GPU. With
+    /// `deterministic` it is the same for every run of a seed, config and backend
+    pub fn run_hash(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> u64 {
+        deterministic::run_hash(&self.read_map(device, queue), &self.read_particles(device, queue))
+    }
+
+    /// Every pipeline a simulation of `config` builds on a device with `limits`,
+    /// with its bind group layouts. For tests/shaders.rs
+    #[doc(hidden)]
+    pub fn pipeline_bindings(config: &Config, limits: &wgpu::Limits) -> Vec<PipelineBindings> {
+        let config = &Config { max_particles: config.capacity(), ..config.clone() };
+        let mut pipelines = compute_bindings(config);
+        pipelines.extend(deterministic::pipeline_bindings(config));
+        pipelines.extend(lifecycle::pipeline_bindings(config));
+        pipelines.extend(metrics::pipeline_bindings(config, limits.max_compute_workgroups_per_dimension));
+        pipelines.extend(render::pipeline_bindings());
+        pipelines
+    }
+}
@@ -0,0 +1,342 @@
+//! Initial particle positions and velocities.
+//!
+//! A `Spawn` picks where particles start (`Pattern`), which way they move
+//! (`Velocity`) and their preference (`Species`), the seed comes from
+//! `Config::seed` so a run can be repeated.
+
+use rand::{ distributions::Uniform, rngs::StdRng, SeedableRng, Rng};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::particle::Particle;
+
+/// Where particles are placed, positions are in clip space `[-1, 1]`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Anywhere in the domain
+    Uniform,
+    /// Filled disc around the centre
+    Disc { radius: f32 },
+    /// Band of `width` around a circle of `radius`
+    Ring { radius: f32, width: f32 },
+    /// Everything starts at a single point
+    Point { x: f32, y: f32 },
+    /// `count` discs of `radius` at random places
+    Clusters { count: u32, radius: f32 },
+    /// Normal distribution around the centre
+    Gaussian { sigma: f32 },
+    /// Proportional to the values of a density map, e.g. an image's brightness
+    Density(DensityMap),
+}
+
+/// Which way particles move at the start, relative to the centre they were spawned around
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Velocity {
+    Inward,
+    Outward,
+    Random,
+    Tangential,
+}
+
+/// What goes into the preference slot of each particle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Species {
+    /// 0 or 1 with equal odds
+    Random,
+    /// The red, green or blue value `[0, 1]` of the cell a particle was spawned
+    /// in, needs a density map with colours and falls back to `Random` otherwise
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spawn {
+    pub pattern: Pattern,
+    pub velocity: Velocity,
+    pub species: Species,
+}
+
+impl Default for Spawn {
+    fn default() -> Self {
+        Spawn {
+            pattern: Pattern::Disc { radius: 0.5 },
+            velocity: Velocity::Inward,
+            species: Species::Random,
+        }
+    }
+}
+
+/// A `width` x `height` grid of non negative weights, row 0 at the top like the trail map
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityMap {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+    // Colour of every cell, used by `Species`
+    pub colours: Option<Vec<[f32; 3]>>,
+}
+
+// Spread of the particles around a point burst
+const POINT_RADIUS: f32 = 0.01;
+
+// Cluster centres stay this far inside the domain
+const CLUSTER_EXTENT: f32 = 0.7;
+
+/// Uniform point in a disc of `radius`, by rejection so the disc default matches older runs
+fn sample_disc(rng: &mut StdRng, unif: Uniform<f32>, radius: f32) -> [f32; 2] {
+    loop {
+        let x = rng.sample(unif);
+        let y = rng.sample(unif);
+        if x * x + y * y < 1.0 {
+            return [x * radius, y * radius];
+        }
+    }
+}
+
+impl DensityMap {
+
+    /// Brightness of every pixel as weights, scaled by alpha so transparent
+    /// areas stay empty, and the pixel colours for `Species`
+    pub fn from_png<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut buffer = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+
+        let channels = info.color_type.samples();
+        let pixels = buffer[..info.buffer_size()].chunks(info.line_size)
+            .flat_map(|line| line[..info.width as usize * channels].chunks(channels))
+            .map(|p| {
+                let c = |i: usize| p[i] as f32 / 255.0;
+                match info.color_type {
+                    png::ColorType::Grayscale => ([c(0); 3], 1.0),
+                    png::ColorType::GrayscaleAlpha => ([c(0); 3], c(1)),
+                    png::ColorType::Rgb => ([c(0), c(1), c(2)], 1.0),
+                    _ => ([c(0), c(1), c(2)], c(3)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(DensityMap {
+            width: info.width as usize,
+            height: info.height as usize,
+            values: pixels.iter().map(|([r, g, b], a)| (0.2126 * r + 0.7152 * g + 0.0722 * b) * a).collect(),
+            colours: Some(pixels.into_iter().map(|(colour, _)| colour).collect()),
+        })
+    }
+
+    /// Cumulative weights used to pick a cell, `None` when the map is empty
+    fn cumulative(&self) -> Option<Vec<f64>> {
+        // Summed in f64, an f32 total stops growing on large maps and starves the last cells
+        let mut total = 0.0f64;
+        let cumulative = self.values.iter().map(|v| { total += v.max(0.0) as f64; total }).collect::<Vec<_>>();
+        if total > 0.0 && cumulative.len() == self.width * self.height { Some(cumulative) } else { None }
+    }
+
+    /// A position inside a cell picked by weight, and that cell's index
+    fn sample(&self, rng: &mut StdRng, cumulative: &[f64]) -> ([f32; 2], usize) {
+        let total = cumulative[cumulative.len() - 1];
+        let target = rng.gen::<f64>() * total;
+        let cell = cumulative.partition_point(|&c| c <= target).min(cumulative.len() - 1);
+        let column = (cell % self.width) as f32 + rng.gen::<f32>();
+        let row = (cell / self.width) as f32 + rng.gen::<f32>();
+        ([
+            column / self.width as f32 * 2.0 - 1.0,
+            1.0 - row / self.height as f32 * 2.0,
+        ], cell)
+    }
+}
+
+impl Spawn {
+
+    pub fn generate(&self, num_particles: u32, speed: f32, seed: u64) -> Vec<Particle> {
+
+        let mut particles = vec![ Particle::default(); num_particles as usize];
+        let mut rng = StdRng::seed_from_u64(seed);
+        let unif = Uniform::new_inclusive(-1.0f32, 1.0);
+
+        // Cluster centres are drawn before any particle
+        let centres = match self.pattern {
+            Pattern::Clusters { count, .. } => (0..count.max(1))
+                .map(|_| [rng.sample(unif) * CLUSTER_EXTENT, rng.sample(unif) * CLUSTER_EXTENT])
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        // An empty density map falls back to the whole domain
+        let cumulative = match &self.pattern {
+            Pattern::Density(map) => map.cumulative(),
+            _ => None,
+        };
+
+        for particle in particles.iter_mut() {
+
+            // Cell of the density map the particle landed in, for its colour
+            let mut cell = None;
+
+            let (position, centre) = match &self.pattern {
+                Pattern::Uniform => ([rng.sample(unif), rng.sample(unif)], [0.0, 0.0]),
+                Pattern::Disc { radius } => (sample_disc(&mut rng, unif, *radius), [0.0, 0.0]),
+                Pattern::Ring { radius, width } => {
+                    let angle = rng.gen::<f32>() * 2.0 * PI;
+                    let r = radius + rng.sample(unif) * width * 0.5;
+                    ([angle.cos() * r, angle.sin() * r], [0.0, 0.0])
+                }
+                Pattern::Point { x, y } => {
+                    let [dx, dy] = sample_disc(&mut rng, unif, POINT_RADIUS);
+                    ([x + dx, y + dy], [*x, *y])
+                }
+                Pattern::Clusters { radius, .. } => {
+                    let centre = centres[rng.gen_range(0, centres.len())];
+                    let [dx, dy] = sample_disc(&mut rng, unif, *radius);
+                    ([centre[0] + dx, centre[1] + dy], centre)
+                }
+                Pattern::Gaussian { sigma } => loop {
+                    // Box-Muller, redrawn when it lands outside the domain
+                    let u = rng.gen::<f32>().max(f32::MIN_POSITIVE);
+                    let angle = rng.gen::<f32>() * 2.0 * PI;
+                    let r = sigma * (-2.0 * u.ln()).sqrt();
+                    let (x, y) = (angle.cos() * r, angle.sin() * r);
+                    if x.abs() <= 1.0 && y.abs() <= 1.0 {
+                        break ([x, y], [0.0, 0.0]);
+                    }
+                },
+                Pattern::Density(map) => match &cumulative {
+                    Some(cumulative) => {
+                        let (position, index) = map.sample(&mut rng, cumulative);
+                        cell = Some(index);
+                        (position, [0.0, 0.0])
+                    }
+                    None => ([rng.sample(unif), rng.sample(unif)], [0.0, 0.0]),
+                },
+            };
+
+            let [dx, dy] = [position[0] - centre[0], position[1] - centre[1]];
+            let direction = match self.velocity {
+                Velocity::Inward => [-dx, -dy],
+                Velocity::Outward => [dx, dy],
+                Velocity::Random => [0.0, 0.0],
+                Velocity::Tangential => [-dy, dx],
+            };
+
+            particle.pos_x = position[0];
+            particle.pos_y = position[1];
+            particle.sense_distance = 0.04;
+            particle.vel_x = direction[0] * speed + rng.sample(unif) * speed;
+            particle.vel_y = direction[1] * speed + rng.sample(unif) * speed;
+
+            let colour = match (&self.pattern, cell) {
+                (Pattern::Density(DensityMap { colours: Some(colours), .. }), Some(cell)) => Some(colours[cell]),
+                _ => None,
+            };
+            particle.preference = match (self.species, colour) {
+                (Species::Red, Some(colour)) => colour[0],
+                (Species::Green, Some(colour)) => colour[1],
+                (Species::Blue, Some(colour)) => colour[2],
+                _ => {
+                    let mut v = (rng.sample(unif)+1.0)/2.0;
+                    if v < 0.5 { v = 0.0;  }
+                    else { v = 1.0; }
+                    v
+                }
+            };
+        }
+
+        particles
+    }
+}
+
+/// Numbers after the `:` in `name:a,b`, `defaults` fills any that are left out
+pub(crate) fn arguments(args: Option<&str>, defaults: &[f32]) -> Result<Vec<f32>, String> {
+    let mut values = defaults.to_vec();
+    if let Some(args) = args {
+        let given = args.split(',').map(|a| a.trim().parse::<f32>().map_err(|_| format!("invalid number {}", a))).collect::<Result<Vec<_>, _>>()?;
+        if given.len() > defaults.len() {
+            return Err(format!("expected at most {} numbers, got {}", defaults.len(), given.len()));
+        }
+        values[..given.len()].copy_from_slice(&given);
+    }
+    Ok(values)
+}
+
+/// Parses `uniform`, `disc[:radius]`, `ring[:radius,width]`, `point[:x,y]`,
+/// `clusters[:count,radius]`, `gaussian[:sigma]` and `image:FILE.png`, the
+/// image is loaded right away
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = match s.split_once(':') {
+            Some((name, args)) => (name, Some(args)),
+            None => (s, None),
+        };
+        Ok(match name {
+            "uniform" => {
+                arguments(args, &[])?;
+                Pattern::Uniform
+            }
+            "disc" => {
+                let a = arguments(args, &[0.5])?;
+                Pattern::Disc { radius: a[0] }
+            }
+            "ring" => {
+                let a = arguments(args, &[0.5, 0.05])?;
+                Pattern::Ring { radius: a[0], width: a[1] }
+            }
+            "point" => {
+                let a = arguments(args, &[0.0, 0.0])?;
+                Pattern::Point { x: a[0], y: a[1] }
+            }
+            "clusters" => {
+                let a = arguments(args, &[5.0, 0.1])?;
+                if a[0] < 1.0 || a[0].fract() != 0.0 {
+                    return Err(format!("cluster count must be a positive whole number, got {}", a[0]));
+                }
+                Pattern::Clusters { count: a[0] as u32, radius: a[1] }
+            }
+            "gaussian" => {
+                let a = arguments(args, &[0.25])?;
+                Pattern::Gaussian { sigma: a[0] }
+            }
+            "image" => {
+                let path = args.ok_or("image needs a file, e.g. image:maze.png")?;
+                Pattern::Density(DensityMap::from_png(path).map_err(|e| format!("unable to load {}: {}", path, e))?)
+            }
+            _ => return Err(format!("unknown spawn pattern {}, expected uniform, disc, ring, point, clusters, gaussian or image", name)),
+        })
+    }
+}
+
+impl FromStr for Velocity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "inward" => Velocity::Inward,
+            "outward" => Velocity::Outward,
+            "random" => Velocity::Random,
+            "tangential" => Velocity::Tangential,
+            _ => return Err(format!("unknown velocity {}, expected inward, outward, random or tangential", s)),
+        })
+    }
+}
+
+impl FromStr for Species {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "random" => Species::Random,
+            "red" => Species::Red,
+            "green" => Species::Green,
+            "blue" => Species::Blue,
+            _ => return Err(format!("unknown species {}, expected random, red, green or blue", s)),
+        })
+    }
+}
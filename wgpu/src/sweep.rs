@@ -0,0 +1,229 @@
+//! Runs the simulation off screen over a grid of parameter values.
+//!
+//! Every combination gets a directory of its own under the output directory
+//! holding the final map as `map.png` and `map.npy`, its `metrics.csv` and the
+//! `config.txt` it ran with. `sweep.csv` has a row per run with the swept
+//! values and its final measurement, and `contact_sheet.png` puts all the
+//! final maps side by side, the last swept parameter changing along a row.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::export::write_npy;
+use crate::metrics::{Measurement, MetricsLog};
+use crate::reference::colour_map;
+use crate::simulation::Simulation;
+
+// Longest side of a map on the contact sheet, larger maps are shrunk to it
+const SHEET_TILE: usize = 256;
+// Black border between the maps on the contact sheet
+const SHEET_GAP: usize = 4;
+
+/// Values one parameter takes, written `name=from:to:count` for evenly spaced
+/// values or `name=a,b,c` for a list
+#[derive(Clone, Debug, PartialEq)]
+pub struct Range {
+    pub param: String,
+    pub values: Vec<f64>,
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (param, values) = s.split_once('=').ok_or_else(|| format!("expected name=from:to:count or name=a,b,c, got {}", s))?;
+        if Config::default().get(param).is_none() {
+            return Err(format!("unknown parameter {}, expected one of {}", param, Config::PARAMS.join(", ")));
+        }
+
+        let number = |v: &str| v.trim().parse::<f64>().map_err(|e| format!("invalid value {} for {} ({})", v, param, e));
+        let values = match values.split(':').collect::<Vec<_>>()[..] {
+            [from, to, count] => {
+                let (from, to) = (number(from)?, number(to)?);
+                let count: usize = count.trim().parse().map_err(|e| format!("invalid count {} for {} ({})", count, param, e))?;
+                match count {
+                    0 => return Err(format!("{} needs at least one value", param)),
+                    1 => vec![from],
+                    _ => (0..count).map(|i| from + (to - from) * i as f64 / (count - 1) as f64).collect(),
+                }
+            }
+            [_] => values.split(',').map(number).collect::<Result<_, _>>()?,
+            _ => return Err(format!("expected from:to:count for {}, got {}", param, values)),
+        };
+
+        Ok(Range { param: param.to_string(), values })
+    }
+}
+
+/// A grid of runs, each `steps` long starting from `base` with the ranges applied
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub base: Config,
+    pub ranges: Vec<Range>,
+    pub steps: u32,
+    /// Simulation steps between rows of each run's `metrics.csv`, at least 1
+    pub metrics_every: u32,
+    pub dir: PathBuf,
+}
+
+/// The outcome of one run, as written to `sweep.csv`
+#[derive(Clone, Debug)]
+pub struct RunResult {
+    pub dir: PathBuf,
+    /// One per range, in order
+    pub values: Vec<f64>,
+    pub measurement: Measurement,
+    /// `Simulation::run_hash` of the final state
+    pub hash: u64,
+}
+
+impl Sweep {
+
+    /// Every combination of values, the last range changing fastest
+    pub fn combinations(&self) -> Vec<Vec<f64>> {
+        self.ranges.iter().fold(vec![Vec::new()], |combinations, range| {
+            combinations.iter().flat_map(|combination| range.values.iter().map(move |&v| {
+                let mut next = combination.clone();
+                next.push(v);
+                next
+            })).collect()
+        })
+    }
+
+    /// The config of one combination, checked before anything is run
+    pub fn config(&self, values: &[f64]) -> Result<Config, String> {
+        let mut config = self.base.clone();
+        for (range, &value) in self.ranges.iter().zip(values) {
+            config.set(&range.param, value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Runs every combination in turn, printing progress, and writes the summary
+    /// and contact sheet once they are all done
+    pub fn run(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<RunResult>, String> {
+
+        if self.metrics_every == 0 {
+            return Err("metrics_every must be at least 1".to_string());
+        }
+        let combinations = self.combinations();
+        let configs = combinations.iter().map(|values| self.config(values)).collect::<Result<Vec<_>, _>>()?;
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Unable to create {}: {}", self.dir.display(), e))?;
+
+        let mut results = Vec::new();
+        let mut maps = Vec::new();
+        let total = combinations.len();
+        for (i, (values, config)) in combinations.into_iter().zip(configs).enumerate() {
+            let dir = self.dir.join(format!("run_{:04}", i));
+            let label = self.ranges.iter().zip(&values).map(|(r, v)| format!("{}={}", r.param, v)).collect::<Vec<_>>().join(" ");
+            println!("Run {} of {}: {}", i + 1, total, label);
+
+            let (measurement, map, hash) = self.run_one(device, queue, config, &dir)
+                .map_err(|e| format!("Run {} failed writing to {}: {}", i, dir.display(), e))?;
+            results.push(RunResult { dir, values, measurement, hash });
+            maps.push(map);
+        }
+
+        self.write_summary(&results).map_err(|e| format!("Unable to write sweep.csv: {}", e))?;
+        let columns = self.ranges.last().map_or(1, |r| r.values.len());
+        write_contact_sheet(&self.dir.join("contact_sheet.png"), &maps, columns)
+            .map_err(|e| format!("Unable to write contact_sheet.png: {}", e))?;
+
+        Ok(results)
+    }
+
+    /// Steps one config to the end, returning its final measurement, map and run hash
+    fn run_one(&self, device: &wgpu::Device, queue: &wgpu::Queue, config: Config, dir: &Path) -> io::Result<(Measurement, (usize, Vec<f32>), u64)> {
+        fs::create_dir_all(dir)?;
+        write_config(&dir.join("config.txt"), &config)?;
+
+        let map_fidelity = config.map_fidelity as usize;
+        let mut simulation = Simulation::new(device, queue, config).map_err(io::Error::other)?;
+        let mut log = MetricsLog::create(&dir.join("metrics.csv"))?;
+
+        let mut measurement = simulation.measure(device, queue);
+        log.write(&measurement)?;
+        for step in 1..=self.steps {
+            let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            simulation.step(&mut command_encoder);
+            queue.submit(Some(command_encoder.finish()));
+
+            if step % self.metrics_every == 0 || step == self.steps {
+                measurement = simulation.measure(device, queue);
+                log.write(&measurement)?;
+            }
+        }
+
+        let map = simulation.read_map(device, queue);
+        write_png(&dir.join("map.png"), map_fidelity, map_fidelity, &colour_map(&map))?;
+        write_npy(BufWriter::new(File::create(dir.join("map.npy"))?), &[map_fidelity, map_fidelity], &map)?;
+        let hash = simulation.run_hash(device, queue);
+        Ok((measurement, (map_fidelity, map), hash))
+    }
+
+    fn write_summary(&self, results: &[RunResult]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(self.dir.join("sweep.csv"))?);
+        let params = self.ranges.iter().map(|r| format!("{},", r.param)).collect::<String>();
+        writeln!(writer, "run,{}{},run_hash", params, Measurement::CSV_HEADER)?;
+        for result in results {
+            let run = result.dir.file_name().unwrap().to_string_lossy();
+            let values = result.values.iter().map(|v| format!("{},", v)).collect::<String>();
+            writeln!(writer, "{},{}{},{:016x}", run, values, result.measurement.csv_row(), result.hash)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Every parameter `Config::set` takes as `name = value`, then where the run started from
+fn write_config(path: &Path, config: &Config) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for param in Config::PARAMS {
+        writeln!(writer, "{} = {}", param, config.get(param).unwrap())?;
+    }
+    writeln!(writer, "spawn = {:?}", config.spawn)?;
+    writeln!(writer, "map_init = {:?}", config.map_init)?;
+    writer.flush()
+}
+
+fn write_png(path: &Path, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgb)?;
+    Ok(())
+}
+
+/// The maps, each with its size, in a grid `columns` wide. Every map is averaged
+/// down to the same tile, at most `SHEET_TILE` and no larger than the biggest map
+fn write_contact_sheet(path: &Path, maps: &[(usize, Vec<f32>)], columns: usize) -> io::Result<()> {
+    let tile = maps.iter().map(|(size, _)| *size).max().unwrap_or(1).min(SHEET_TILE);
+    let rows = maps.len().div_ceil(columns);
+
+    let width = columns * tile + (columns + 1) * SHEET_GAP;
+    let height = rows * tile + (rows + 1) * SHEET_GAP;
+    let mut sheet = vec![0u8; width * height * 3];
+
+    for (i, (size, map)) in maps.iter().enumerate() {
+        // Tile pixel t covers the cells from t * size / tile up to the next one's start
+        let span = |t: usize| t * size / tile..((t + 1) * size / tile).max(t * size / tile + 1);
+        let shrunk: Vec<f32> = (0..tile * tile).map(|t| {
+            let (rows, cols) = (span(t / tile), span(t % tile));
+            let cells = (rows.len() * cols.len()) as f32;
+            rows.flat_map(|row| cols.clone().map(move |col| row * size + col)).map(|c| map[c]).sum::<f32>() / cells
+        }).collect();
+        let rgb = colour_map(&shrunk);
+
+        let left = SHEET_GAP + (i % columns) * (tile + SHEET_GAP);
+        let top = SHEET_GAP + (i / columns) * (tile + SHEET_GAP);
+        for row in 0..tile {
+            let start = ((top + row) * width + left) * 3;
+            sheet[start..start + tile * 3].copy_from_slice(&rgb[row * tile * 3..(row + 1) * tile * 3]);
+        }
+    }
+
+    write_png(path, width, height, &sheet)
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+// -------------------------------------------------------------------------------------------------
+// Utility functions
+// -------------------------------------------------------------------------------------------------
+
+/// Invocations per workgroup of every compute kernel, `@workgroup_size` in the shaders
+pub const WORKGROUP_SIZE: u32 = 64;
+
+/// Workgroups needed to cover `count` invocations, the kernels skip the spare ones at the end
+pub fn workgroups ( count : u32 ) -> u32 {
+    count.div_ceil(WORKGROUP_SIZE)
+}
+
+/// `workgroups` as rows of at most `max_per_dimension`, for counts one row can't
+/// cover. Kernels flatten it back with `invocation_index` from common.wgsl
+pub fn dispatch_grid ( count : u32, max_per_dimension : u32 ) -> (u32, u32) {
+    let rows = workgroups(count).div_ceil(max_per_dimension).max(1);
+    (workgroups(count).div_ceil(rows), rows)
+}
+
+/// `limits` with the storage binding size and workgroups per dimension raised to
+/// what `adapter` offers, so the simulation isn't held to the downlevel defaults
+pub fn using_buffer_limits ( limits : wgpu::Limits, adapter : &wgpu::Limits ) -> wgpu::Limits {
+    wgpu::Limits {
+        max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size.max(adapter.max_storage_buffer_binding_size),
+        max_compute_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension.max(adapter.max_compute_workgroups_per_dimension),
+        ..limits
+    }
+}
+
+pub fn make_buffer ( device : &wgpu::Device, source : &[f32] ) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents: bytemuck::cast_slice(source),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::UNIFORM,
+    })
+}
+
+/// Source of a shader in `src`, with `common.wgsl` prepended for the shared structs.
+/// Nothing is checked at build time, tests/shaders.rs validates every shader with
+/// naga and errors left there only show up when wgpu creates the module
+macro_rules! shader {
+    ($file:literal) => {
+        concat!(include_str!("common.wgsl"), "\n", include_str!($file))
+    };
+}
+pub(crate) use shader;
+
+pub fn make_shader ( device : &wgpu::Device, source : &str ) -> wgpu::ShaderModule {
+    device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source))
+    })
+}
+
+/// Reads `file` from `dir` with `common.wgsl` prepended, as `shader!` does at build time
+pub fn read_shader ( dir : &Path, file : &str ) -> Result<String, String> {
+    let read = |name : &str| fs::read_to_string(dir.join(name)).map_err(|e| format!("{}: {}", name, e));
+    Ok(format!("{}\n{}", read("common.wgsl")?, read(file)?))
+}
+
+/// Runs `build` and returns what it made, or the validation errors it raised
+/// instead of letting them panic through the device's error handler
+pub fn validated<T> ( device : &wgpu::Device, build : impl FnOnce() -> T ) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let built = build();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error.to_string()),
+        None => Ok(built),
+    }
+}
+
+/// Compiles `file` from `dir` and hands it to `build`, errors name the file
+pub fn reload_shader<T> ( device : &wgpu::Device, dir : &Path, file : &str, build : impl FnOnce(&wgpu::ShaderModule) -> T ) -> Result<T, String> {
+    let source = read_shader(dir, file)?;
+    validated(device, || build(&make_shader(device, &source))).map_err(|e| format!("{}: {}", file, e))
+}
+
+/// Buffer bindings of `bind_type`, each at least as large as its entry in `sizes`
+pub fn buffer_bindings (bind_type : wgpu::BufferBindingType, sizes : &[u32]) -> Vec<wgpu::BindingType> {
+    sizes.iter().map(|size| wgpu::BindingType::Buffer {
+        ty: bind_type,
+        has_dynamic_offset: false,
+        min_binding_size: wgpu::BufferSize::new(*size as _ ),
+    }).collect()
+}
+
+/// A bind group layout holding `bindings`, numbered in order
+pub fn make_layout (device : &wgpu::Device, stage : wgpu::ShaderStages, bindings : &[wgpu::BindingType]) -> wgpu::BindGroupLayout {
+
+    // Construct the entieries of the bind group
+    let enties = bindings.iter().enumerate().map(|(i, ty)| wgpu::BindGroupLayoutEntry {
+        count: None,
+        binding: i as u32,
+        visibility: stage,
+        ty: *ty,
+    }).collect::<Vec<_>>();
+
+    // Create the layout
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: enties.as_slice(),
+        label: None,
+    })
+
+}
+
+/// The bind group layouts of a pipeline as it is built, group by group, with the
+/// entry points using them. tests/shaders.rs holds every shader to these
+#[derive(Clone, Debug)]
+pub struct PipelineBindings {
+    pub shader: &'static str,
+    pub entry_points: Vec<&'static str>,
+    pub groups: Vec<Vec<wgpu::BindingType>>,
+}
+
+impl PipelineBindings {
+    pub fn new (shader : &'static str, entry_points : &[&'static str], groups : Vec<Vec<wgpu::BindingType>>) -> Self {
+        PipelineBindings { shader, entry_points: entry_points.to_vec(), groups }
+    }
+}
+
+pub fn make_pipeline_layout (device : &wgpu::Device, layouts : &[&wgpu::BindGroupLayout]) -> wgpu::PipelineLayout {
+
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: layouts,
+        push_constant_ranges: &[],
+    })
+}
+
+pub fn make_compute_pipeline (device : &wgpu::Device, layout : &wgpu::PipelineLayout, shader : &wgpu::ShaderModule) -> wgpu::ComputePipeline {
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        module: shader,
+        entry_point: "main",
+    })
+}
+
+pub fn make_render_pipeline (device : &wgpu::Device, format: wgpu::TextureFormat, layout : &wgpu::PipelineLayout, shader : &wgpu::ShaderModule, buffers : &[wgpu::VertexBufferLayout]) -> wgpu::RenderPipeline {
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "main_vs",
+            buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "main_fs",
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+pub fn make_bind_group (device : &wgpu::Device, layout : &wgpu::BindGroupLayout, buffers : &[&wgpu::Buffer]) -> wgpu::BindGroup {
+
+    let mut entries = Vec::<wgpu::BindGroupEntry>::new();
+    for (i, buffer) in buffers.iter().enumerate() {
+        entries.push(wgpu::BindGroupEntry {
+            binding: i as u32,
+            resource: buffer.as_entire_binding()
+        });
+    }
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: None,
+    })
+
+}
+
+/// Which adapter to run on, from `--backend` and `--adapter`. Anything not chosen
+/// here is left to `WGPU_BACKEND` and `WGPU_ADAPTER_NAME`
+#[derive(Clone, Debug, Default)]
+pub struct AdapterChoice {
+    /// Backends to look for adapters on
+    pub backends: Option<wgpu::Backends>,
+    /// Part of the adapter's name, ignoring case
+    pub name: Option<String>,
+    /// Run on the software adapter, whatever else there is
+    pub fallback: bool,
+}
+
+impl AdapterChoice {
+
+    /// The chosen backends, then `WGPU_BACKEND`, then all of them
+    pub fn backends(&self) -> wgpu::Backends {
+        self.backends.or_else(wgpu::util::backend_bits_from_env).unwrap_or_else(wgpu::Backends::all)
+    }
+
+    /// Whether a particular adapter was asked for, rather than the default
+    pub fn is_explicit(&self) -> bool {
+        self.fallback || self.name.is_some()
+    }
+
+    /// The adapter asked for, able to present to `surface` if there is one. With
+    /// nothing asked for this is the default adapter
+    pub async fn find(&self, instance : &wgpu::Instance, surface : Option<&wgpu::Surface>) -> Result<wgpu::Adapter, String> {
+        let backends = self.backends();
+
+        if self.fallback {
+            return instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: true,
+                compatible_surface: surface,
+            }).await.ok_or_else(|| format!("No software adapter on {:?}", backends));
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(name) = &self.name {
+            let (matching, others): (Vec<_>, Vec<_>) = instance.enumerate_adapters(backends)
+                .partition(|a| a.get_info().name.to_lowercase().contains(&name.to_lowercase()));
+            let first = match matching.first() {
+                Some(adapter) => adapter.get_info().name,
+                None => {
+                    let found = others.iter().map(|a| format!("{} ({:?})", a.get_info().name, a.get_info().backend)).collect::<Vec<_>>();
+                    return Err(format!("No adapter matching \"{}\" on {:?}, found: {}", name, backends, if found.is_empty() { "none".to_string() } else { found.join(", ") }));
+                }
+            };
+            return matching.into_iter().find(|a| surface.is_none_or(|s| a.is_surface_supported(s)))
+                .ok_or_else(|| format!("{} can't present to this window", first));
+        }
+        #[cfg(target_arch = "wasm32")]
+        if self.name.is_some() {
+            return Err("Adapters can't be picked by name on the web".to_string());
+        }
+
+        wgpu::util::initialize_adapter_from_env_or_default(instance, backends, surface).await
+            .ok_or_else(|| format!("No GPU adapters found on {:?}", backends))
+    }
+}
+
+/// Creates a device without any window or surface, for running the simulation off screen.
+/// Honours the same `WGPU_BACKEND` and `WGPU_ADAPTER_NAME` variables as the windowed app
+pub fn request_headless_device() -> Result<(wgpu::Device, wgpu::Queue), String> {
+    request_headless_device_on(&AdapterChoice::default())
+}
+
+/// `request_headless_device` on the adapter chosen, the software adapter only
+/// stands in for the default one
+pub fn request_headless_device_on(choice : &AdapterChoice) -> Result<(wgpu::Device, wgpu::Queue), String> {
+
+    let instance = wgpu::Instance::new(choice.backends());
+
+    let adapter = match pollster::block_on(choice.find(&instance, None)) {
+        Ok(adapter) => adapter,
+        Err(e) if choice.is_explicit() => return Err(e),
+        // The software adapter stands in when there is no other
+        Err(_) => pollster::block_on(AdapterChoice { fallback: true, ..choice.clone() }.find(&instance, None))
+            .map_err(|_| "No suitable GPU adapters found on the system!".to_string())?,
+    };
+
+    request_device(&adapter)
+}
+
+/// A device on `adapter` with the limits the simulation asks for, as `request_headless_device` makes
+pub fn request_device(adapter : &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), String> {
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            features: wgpu::Features::empty(),
+            limits: using_buffer_limits(wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()), &adapter.limits()),
+        },
+        None,
+    ))
+    .map_err(|e| format!("Unable to create a device on {}: {}", adapter.get_info().name, e))
+}
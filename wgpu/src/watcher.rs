@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Notices edits to the `.wgsl` files in a directory.
+///
+/// Polled once a frame, a handful of `stat` calls is cheap enough that no
+/// file system notifications are needed.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Latest modification time of any shader in `dir`
+fn latest(dir: &Path) -> Option<SystemTime> {
+    fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "wgsl"))
+        .filter_map(|entry| entry.metadata().and_then(|meta| meta.modified()).ok())
+        .max()
+}
+
+impl ShaderWatcher {
+
+    pub fn new(dir: PathBuf) -> Self {
+        let modified = latest(&dir);
+        ShaderWatcher { dir, modified }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether a shader was saved since the last call
+    pub fn changed(&mut self) -> bool {
+        let modified = latest(&self.dir);
+        if modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! The adapter for tests that need a GPU. It is the software adapter so every
+//! machine runs them the same way. Not every machine has one, so those tests are
+//! `#[ignore]`d and `cargo test -- --ignored` runs them, failing without it.
+
+use particles::util::AdapterChoice;
+
+/// The software adapter, asked for explicitly so no other adapter stands in for it
+pub fn software_adapter() -> wgpu::Adapter {
+    let choice = AdapterChoice { fallback: true, ..AdapterChoice::default() };
+    let instance = wgpu::Instance::new(choice.backends());
+    pollster::block_on(choice.find(&instance, None)).unwrap_or_else(|e| panic!("{}", e))
+}
@@ -0,0 +1,30 @@
+//! Two deterministic runs from the same seed end in the same bits on the
+//! software adapter.
+
+mod common;
+
+use particles::{util, Config, Simulation};
+
+const STEPS: u32 = 50;
+
+fn run(device: &wgpu::Device, queue: &wgpu::Queue, config: Config) -> u64 {
+    let mut simulation = Simulation::new(device, queue, config).unwrap();
+    for _ in 0..STEPS {
+        let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        simulation.step(&mut command_encoder);
+        queue.submit(Some(command_encoder.finish()));
+    }
+    simulation.run_hash(device, queue)
+}
+
+#[test]
+#[ignore = "needs the software adapter, run with cargo test -- --ignored"]
+fn same_seed_same_hash() {
+    let (device, queue) = util::request_device(&common::software_adapter()).unwrap();
+
+    // Crowded enough that many particles share a cell every step
+    let config = Config { num_particles: 20000, map_fidelity: 64, deterministic: true, ..Config::default() };
+    let first = run(&device, &queue, config.clone());
+    assert_eq!(run(&device, &queue, config.clone()), first);
+    assert_ne!(run(&device, &queue, Config { seed: config.seed + 1, ..config }), first);
+}
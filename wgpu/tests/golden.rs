@@ -0,0 +1,146 @@
+//! Golden image tests: fixed seeds are stepped on the CPU reference and the
+//! trail map, drawn with the app's colour ramp, is compared against the PNGs
+//! in `tests/golden`. `shaders_follow_golden_runs` steps the same configs
+//! through `Simulation` on the software adapter and holds them to the
+//! reference, so a change in behaviour on either side shows up here.
+//!
+//! That test is ignored by default, so a plain `cargo test` only checks the
+//! CPU reference and a change to compute.wgsl is caught only by
+//! `cargo test -- --ignored` on a machine with the software adapter.
+//!
+//! After an intended change, regenerate the images with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and check them in.
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use particles::map_init::MapInit;
+use particles::reference::{colour_map, Reference};
+use particles::spawn::{Pattern, Spawn};
+use particles::{parity, util, Config};
+
+// Pixels may differ by up to this much per channel, to allow for other
+// platforms' sin, cos and atan2, and only this fraction of pixels may exceed it
+const CHANNEL_TOLERANCE: u8 = 8;
+const PIXEL_TOLERANCE: f64 = 0.01;
+
+const MAP_FIDELITY: u32 = 128;
+const STEPS: u32 = 200;
+
+// Steps compared against the GPU, before races between particles in a cell
+// have had time to add up, and how far apart the two may be on average
+const GPU_STEPS: u32 = 5;
+const MEAN_TOLERANCE: f32 = 1e-3;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn write_png(path: &Path, size: u32, rgb: &[u8]) {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), size, size);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(rgb).unwrap();
+}
+
+fn read_png(path: &Path) -> Option<(u32, Vec<u8>)> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let mut reader = decoder.read_info().unwrap();
+    let mut rgb = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgb).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgb, "{} is not RGB", path.display());
+    rgb.truncate(info.buffer_size());
+    Some((info.width, rgb))
+}
+
+/// Runs `config` for `STEPS` and compares the map with `tests/golden/{name}.png`
+fn check(name: &str, config: Config) {
+    let mut reference = Reference::new(Config { map_fidelity: MAP_FIDELITY, ..config });
+    for _ in 0..STEPS {
+        reference.step();
+    }
+    let actual = colour_map(reference.map());
+
+    let path = golden_dir().join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        write_png(&path, MAP_FIDELITY, &actual);
+        return;
+    }
+
+    let (size, expected) = read_png(&path)
+        .unwrap_or_else(|| panic!("{} is missing, create it with UPDATE_GOLDEN=1 cargo test --test golden", path.display()));
+    assert_eq!(size, MAP_FIDELITY, "{} has the wrong size", path.display());
+
+    let differing = expected.chunks(3).zip(actual.chunks(3))
+        .filter(|(e, a)| e.iter().zip(a.iter()).any(|(e, a)| e.abs_diff(*a) > CHANNEL_TOLERANCE))
+        .count();
+    let fraction = differing as f64 / (MAP_FIDELITY * MAP_FIDELITY) as f64;
+
+    if fraction > PIXEL_TOLERANCE {
+        // Keep what was drawn next to the build for a side by side look
+        let failed = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.actual.png", name));
+        write_png(&failed, MAP_FIDELITY, &actual);
+        panic!("{}: {:.2}% of pixels differ from {}, this run is in {}", name, fraction * 100.0, path.display(), failed.display());
+    }
+}
+
+/// Every golden run, by the name of its image
+fn configs() -> Vec<(&'static str, Config)> {
+    vec![
+        ("disc", Config {
+            num_particles: 5000,
+            ..Config::default()
+        }),
+        ("clusters_on_perlin", Config {
+            num_particles: 5000,
+            spawn: Spawn { pattern: Pattern::Clusters { count: 4, radius: 0.15 }, ..Spawn::default() },
+            map_init: MapInit::Perlin { scale: 4.0, amplitude: 0.5 },
+            seed: 7,
+            ..Config::default()
+        }),
+        ("respawn", Config {
+            num_particles: 5000,
+            respawn_chance: 0.01,
+            spawn: Spawn { pattern: Pattern::Uniform, ..Spawn::default() },
+            ..Config::default()
+        }),
+    ]
+}
+
+fn config(name: &str) -> Config {
+    configs().into_iter().find(|(n, _)| *n == name).unwrap().1
+}
+
+#[test]
+fn disc() {
+    check("disc", config("disc"));
+}
+
+#[test]
+fn clusters_on_perlin() {
+    check("clusters_on_perlin", config("clusters_on_perlin"));
+}
+
+#[test]
+fn respawn() {
+    check("respawn", config("respawn"));
+}
+
+#[test]
+#[ignore = "needs the software adapter, run with cargo test -- --ignored"]
+fn shaders_follow_golden_runs() {
+    let (device, queue) = util::request_device(&common::software_adapter()).unwrap();
+
+    for (name, config) in configs() {
+        let config = Config { map_fidelity: MAP_FIDELITY, ..config };
+        for parity in parity::compare(&device, &queue, config, GPU_STEPS).unwrap() {
+            for d in &parity.differences {
+                assert!(d.mean <= MEAN_TOLERANCE, "{} step {}: {} is {} apart on average\n{}", name, parity.step, d.field, d.mean, parity);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Sizing to the device: configs lowered to fit its limits, and dispatches split
+//! into rows of workgroups giving the same result as a single row.
+
+mod common;
+
+use particles::util::{dispatch_grid, WORKGROUP_SIZE};
+use particles::{Config, Particle, Simulation, MAX_MAP_FIDELITY};
+
+const STEPS: u32 = 20;
+
+#[test]
+fn grids_cover_every_invocation() {
+    assert_eq!(dispatch_grid(0, 65535), (0, 1));
+    assert_eq!(dispatch_grid(1000, 65535), (16, 1));
+    assert_eq!(dispatch_grid(65535 * WORKGROUP_SIZE, 65535), (65535, 1));
+
+    for (count, max) in [(65536 * WORKGROUP_SIZE, 65535), (10000, 16), (3_000_000, 1000), (u32::MAX, 65535)] {
+        let (x, y) = dispatch_grid(count, max);
+        assert!(x <= max && y <= max, "{} over {} gave {}x{}", count, max, x, y);
+        assert!(x as u64 * y as u64 * WORKGROUP_SIZE as u64 >= count as u64);
+        // No more than a row's worth of spare workgroups
+        assert!(x as u64 * (y - 1) as u64 * (WORKGROUP_SIZE as u64) < count as u64);
+    }
+}
+
+#[test]
+fn fit_lowers_particles_and_map() {
+    let limits = wgpu::Limits { max_storage_buffer_binding_size: 1 << 20, ..wgpu::Limits::downlevel_defaults() };
+
+    let mut config = Config { num_particles: 100_000, max_particles: 200_000, map_fidelity: 1024, ..Config::default() };
+    let changes = config.fit(&limits);
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    assert_eq!(config.max_particles, (1 << 20) / Particle::SIZE as u32);
+    assert_eq!(config.num_particles, config.max_particles);
+    assert_eq!(config.map_fidelity, 512);
+
+    // Anything that fits is left alone
+    let mut small = Config { num_particles: 1000, max_particles: 1000, map_fidelity: 256, ..Config::default() };
+    assert!(small.fit(&limits).is_empty());
+    assert_eq!(small.max_particles, 1000);
+    assert_eq!(small.map_fidelity, 256);
+}
+
+#[test]
+fn map_fidelity_stays_exact_in_f32() {
+    // A device that could bind a far larger map still gets no more than the shaders index exactly
+    let limits = wgpu::Limits { max_storage_buffer_binding_size: u32::MAX, max_compute_workgroups_per_dimension: 65535, ..wgpu::Limits::downlevel_defaults() };
+    let mut config = Config { map_fidelity: MAX_MAP_FIDELITY, ..Config::default() };
+    assert!(config.fit(&limits).is_empty());
+    assert_eq!(config.map_fidelity, MAX_MAP_FIDELITY);
+
+    let too_fine = Config { map_fidelity: MAX_MAP_FIDELITY + 1, ..Config::default() };
+    assert!(too_fine.validate().is_err());
+}
+
+#[test]
+#[ignore = "needs the software adapter, run with cargo test -- --ignored"]
+fn split_dispatches_match_a_single_row() {
+    let adapter = common::software_adapter();
+
+    // The particles need 313 workgroups and the map 64, a 16 wide limit splits both
+    let config = Config { num_particles: 20000, map_fidelity: 64, deterministic: true, ..Config::default() };
+    let run = |max_compute_workgroups_per_dimension| {
+        let limits = wgpu::Limits { max_compute_workgroups_per_dimension, ..wgpu::Limits::downlevel_defaults() };
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor { label: None, features: wgpu::Features::empty(), limits }, None)).unwrap();
+
+        let mut simulation = Simulation::new(&device, &queue, config.clone()).unwrap();
+        for _ in 0..STEPS {
+            let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            simulation.step(&mut command_encoder);
+            queue.submit(Some(command_encoder.finish()));
+        }
+        (simulation.measure(&device, &queue).particles, simulation.run_hash(&device, &queue))
+    };
+
+    let (particles, hash) = run(16);
+    assert_eq!(particles, config.num_particles);
+    assert_eq!(hash, run(65535).1);
+}
@@ -0,0 +1,101 @@
+//! Graph extraction on hand drawn maps, where the right answer is known.
+
+use particles::network::Network;
+
+const N: usize = 64;
+
+/// A map with the cells `trail` says are set to 1, taking row and column
+fn draw(trail: impl Fn(usize, usize) -> bool) -> Vec<f32> {
+    (0..N * N).map(|i| if trail(i / N, i % N) { 1.0 } else { 0.0 }).collect()
+}
+
+/// Simulation coordinates of the centre of a cell
+fn at(row: usize, col: usize) -> [f32; 2] {
+    let cell = 2.0 / N as f32;
+    [(col as f32 + 0.5) * cell - 1.0, 1.0 - (row as f32 + 0.5) * cell]
+}
+
+#[test]
+fn cross_has_one_junction_and_four_arms() {
+    // Two 3 wide bars from 12 to 52 crossing at 32
+    let map = draw(|r, c| {
+        let bar = |a: usize, b: usize| (31..=33).contains(&a) && (12..=52).contains(&b);
+        bar(r, c) || bar(c, r)
+    });
+    let network = Network::extract(&map, N as u32, 0.5);
+
+    assert_eq!(network.nodes.len(), 5, "{:?}", network);
+    assert_eq!(network.edges.len(), 4);
+    assert_eq!(network.junctions(), 1);
+    assert_eq!(network.components(), 1);
+    assert_eq!(network.nodes.iter().filter(|n| n.degree == 1).count(), 4);
+
+    // Thinning eats a cell or two from each end
+    let cell = 2.0 / N as f32;
+    let length = network.total_length() / cell;
+    assert!((72.0..=82.0).contains(&length), "{}", length);
+}
+
+#[test]
+fn ring_is_a_single_loop() {
+    let map = draw(|r, c| {
+        let d = ((r as f32 - 32.0).powi(2) + (c as f32 - 32.0).powi(2)).sqrt();
+        (19.0..21.5).contains(&d)
+    });
+    let network = Network::extract(&map, N as u32, 0.5);
+
+    assert_eq!(network.nodes.len(), 1, "{:?}", network);
+    assert_eq!(network.edges.len(), 1);
+    assert_eq!(network.edges[0].from, network.edges[0].to);
+    assert_eq!(network.nodes[0].degree, 2);
+
+    let cell = 2.0 / N as f32;
+    let circumference = 2.0 * std::f32::consts::PI * 20.0;
+    let length = network.total_length() / cell;
+    assert!((length - circumference).abs() < 0.1 * circumference, "{} against {}", length, circumference);
+}
+
+#[test]
+fn lines_wrap_around_the_edges() {
+    // A band across the whole map closes on itself
+    let map = draw(|r, _| (30..=32).contains(&r));
+    let network = Network::extract(&map, N as u32, 0.5);
+
+    assert_eq!(network.edges.len(), 1, "{:?}", network);
+    assert_eq!(network.components(), 1);
+    let cell = 2.0 / N as f32;
+    assert!((network.total_length() / cell - N as f32).abs() < 1.0);
+}
+
+#[test]
+fn food_sources_connect_along_the_network() {
+    // A horizontal line and a separate short one below it
+    let map = draw(|r, c| ((20..=22).contains(&r) && (10..=50).contains(&c)) || ((44..=46).contains(&r) && (10..=30).contains(&c)));
+    let network = Network::extract(&map, N as u32, 0.5);
+    assert_eq!(network.components(), 2, "{:?}", network);
+
+    let connectivity = network.connectivity(&[at(21, 10), at(21, 50), at(45, 10)]);
+    assert_eq!(connectivity.pairs, 3);
+    assert_eq!(connectivity.connected_pairs, 1);
+    let cell = 2.0 / N as f32;
+    assert!((connectivity.mean_path_length / cell - 38.0).abs() < 3.0, "{}", connectivity.mean_path_length / cell);
+}
+
+#[test]
+fn exports_are_well_formed() {
+    let map = draw(|r, c| (31..=33).contains(&r) && (12..=52).contains(&c));
+    let network = Network::extract(&map, N as u32, 0.5);
+
+    let mut graphml = Vec::new();
+    network.write_graphml(&mut graphml).unwrap();
+    let graphml = String::from_utf8(graphml).unwrap();
+    assert_eq!(graphml.matches("<node ").count(), network.nodes.len());
+    assert_eq!(graphml.matches("<edge ").count(), network.edges.len());
+    assert!(graphml.trim_end().ends_with("</graphml>"));
+
+    let mut json = Vec::new();
+    network.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains(r#""source": 0, "target": 1"#), "{}", json);
+    assert_eq!(json.matches('{').count(), json.matches('}').count());
+}
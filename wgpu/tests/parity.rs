@@ -0,0 +1,26 @@
+//! The shaders against the CPU reference on the software adapter.
+
+mod common;
+
+use particles::{parity, util, Config};
+
+// The GPU loses the odd trail deposit to races between particles in the same
+// cell, which shifts a few sensor readings, so only averages are held tight
+const MEAN_TOLERANCE: f32 = 1e-3;
+
+#[test]
+#[ignore = "needs the software adapter, run with cargo test -- --ignored"]
+fn shaders_match_reference() {
+    let (device, queue) = util::request_device(&common::software_adapter()).unwrap();
+
+    let config = Config { num_particles: 2000, map_fidelity: 128, ..Config::default() };
+    for parity in parity::compare(&device, &queue, config, 5).unwrap() {
+        // Neither side ever writes these
+        for field in ["sense_distance", "preference", "energy"] {
+            assert_eq!(parity.field(field).unwrap().max, 0.0, "step {}: {}", parity.step, field);
+        }
+        for d in &parity.differences {
+            assert!(d.mean <= MEAN_TOLERANCE, "step {}: {} is {} apart on average\n{}", parity.step, d.field, d.mean, parity);
+        }
+    }
+}
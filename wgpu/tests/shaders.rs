@@ -0,0 +1,197 @@
+//! Parses and validates every shader with naga, then checks the resources
+//! each entry point uses against the bind group layouts the Rust side builds
+//! for its pipeline, so a shader that no longer fits its pipeline fails here
+//! rather than on a GPU.
+
+use std::fs;
+use std::path::PathBuf;
+
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use particles::util::{read_shader, PipelineBindings, WORKGROUP_SIZE};
+use particles::{post, Config, SimulationConstants, Simulation};
+
+/// What a shader declares at a binding, from naga's view of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Binding {
+    Uniform { size: u32 },
+    /// A runtime sized array, checked by the size of one element
+    Array { read_only: bool, stride: u32 },
+    /// Storage of a fixed size
+    Storage { read_only: bool, size: u32 },
+    Texture,
+    Sampler,
+}
+
+/// Every pipeline the crate builds, as described by the modules building them
+fn pipelines() -> Vec<PipelineBindings> {
+    let mut pipelines = Simulation::pipeline_bindings(&Config::default(), &wgpu::Limits::downlevel_defaults());
+    pipelines.extend(post::pipeline_bindings());
+    pipelines
+}
+
+fn source_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src")
+}
+
+/// The source wgpu is handed, `common.wgsl` is prepended to all but post processing and blitting
+fn source(file: &str) -> String {
+    if file == "post.wgsl" || file == "blit.wgsl" {
+        fs::read_to_string(source_dir().join(file)).unwrap()
+    } else {
+        read_shader(&source_dir(), file).unwrap()
+    }
+}
+
+fn parse(file: &str) -> (naga::Module, ModuleInfo) {
+    let source = source(file);
+    let module = naga::front::wgsl::parse_str(&source)
+        .unwrap_or_else(|e| panic!("{} does not parse:\n{}", file, e.emit_to_string(&source)));
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty()).validate(&module)
+        .unwrap_or_else(|e| panic!("{} does not validate: {:?}", file, e));
+    (module, info)
+}
+
+/// What the shader declares at a binding, in the terms of `Binding`
+fn declared(module: &naga::Module, layouter: &naga::proc::Layouter, var: &naga::GlobalVariable) -> Binding {
+    let ty = &module.types[var.ty];
+    match var.space {
+        naga::AddressSpace::Uniform => Binding::Uniform { size: layouter[var.ty].size },
+        naga::AddressSpace::Storage { access } => {
+            let read_only = !access.contains(naga::StorageAccess::STORE);
+            match ty.inner {
+                naga::TypeInner::Array { size: naga::ArraySize::Dynamic, stride, .. } => Binding::Array { read_only, stride },
+                _ => Binding::Storage { read_only, size: layouter[var.ty].size },
+            }
+        }
+        naga::AddressSpace::Handle => match ty.inner {
+            naga::TypeInner::Sampler { .. } => Binding::Sampler,
+            _ => Binding::Texture,
+        },
+        space => panic!("{:?} has no bind group layout equivalent", space),
+    }
+}
+
+/// Whether a layout entry holds what the shader declares, runtime sized arrays
+/// need a whole number of elements
+fn fits(declared: Binding, entry: &wgpu::BindingType) -> bool {
+    use wgpu::BufferBindingType::{Storage, Uniform};
+    let (ty, size) = match entry {
+        wgpu::BindingType::Buffer { ty, min_binding_size, .. } => (*ty, min_binding_size.map_or(0, |size| size.get())),
+        wgpu::BindingType::Texture { .. } => return declared == Binding::Texture,
+        wgpu::BindingType::Sampler(_) => return declared == Binding::Sampler,
+        _ => return false,
+    };
+    match (declared, ty) {
+        (Binding::Uniform { size: declared }, Uniform) => size == declared as u64,
+        (Binding::Array { read_only, stride }, Storage { read_only: layout_read_only }) => read_only == layout_read_only && size > 0 && size % stride as u64 == 0,
+        (Binding::Storage { read_only, size: declared }, Storage { read_only: layout_read_only }) => read_only == layout_read_only && size == declared as u64,
+        _ => false,
+    }
+}
+
+#[test]
+fn shaders_validate() {
+    let pipelines = pipelines();
+    for entry in fs::read_dir(source_dir()).unwrap() {
+        let file = entry.unwrap().file_name().into_string().unwrap();
+        if !file.ends_with(".wgsl") || file == "common.wgsl" {
+            continue;
+        }
+        parse(&file);
+        assert!(pipelines.iter().any(|p| p.shader == file), "{} is not used by any pipeline", file);
+    }
+}
+
+#[test]
+fn bindings_match_layouts() {
+    for pipeline in pipelines() {
+        let file = pipeline.shader;
+        let (module, info) = parse(file);
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(&module.types, &module.constants).unwrap();
+
+        for entry_point in &pipeline.entry_points {
+            let index = module.entry_points.iter().position(|ep| ep.name == *entry_point)
+                .unwrap_or_else(|| panic!("{} has no entry point {}", file, entry_point));
+            let uses = info.get_entry_point(index);
+
+            // Only what the entry point touches has to be in its pipeline's layout
+            for (handle, var) in module.global_variables.iter() {
+                let binding = match &var.binding {
+                    Some(binding) if !uses[handle].is_empty() => binding,
+                    _ => continue,
+                };
+                let name = var.name.as_deref().unwrap_or("?");
+                let entry = pipeline.groups.get(binding.group as usize)
+                    .and_then(|group| group.get(binding.binding as usize))
+                    .unwrap_or_else(|| panic!("{} {}: {} at @group({}) @binding({}) is missing from the layout", file, entry_point, name, binding.group, binding.binding));
+                let declared = declared(&module, &layouter, var);
+                assert!(fits(declared, entry), "{} {}: {} at @group({}) @binding({}) is {:?} but the layout has {:?}", file, entry_point, name, binding.group, binding.binding, declared, entry);
+            }
+        }
+    }
+}
+
+#[test]
+fn workgroup_sizes_match_rust() {
+    let (module, _) = parse("compute.wgsl");
+    let constant = module.constants.iter()
+        .find(|(_, c)| c.name.as_deref() == Some("WORKGROUP_SIZE"))
+        .map(|(_, c)| &c.inner)
+        .unwrap();
+    assert_eq!(*constant, naga::ConstantInner::Scalar { width: 4, value: naga::ScalarValue::Uint(WORKGROUP_SIZE as u64) }, "common.wgsl and util.rs have different workgroup sizes");
+
+    // Kernels are dispatched with `workgroups`, so each covers `WORKGROUP_SIZE`
+    // invocations unless it runs as a single one
+    for pipeline in pipelines() {
+        let (module, _) = parse(pipeline.shader);
+        for ep in module.entry_points.iter().filter(|ep| ep.stage == naga::ShaderStage::Compute) {
+            assert!(ep.workgroup_size == [WORKGROUP_SIZE, 1, 1] || ep.workgroup_size == [1, 1, 1],
+                "{} {} has @workgroup_size{:?}, not {}", pipeline.shader, ep.name, ep.workgroup_size, WORKGROUP_SIZE);
+        }
+    }
+}
+
+/// `snake_case` to the `camelCase` common.wgsl uses
+fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        camel.extend(chars);
+    }
+    camel
+}
+
+#[test]
+fn constants_match_rust() {
+    let (module, _) = parse("compute.wgsl");
+    let (handle, ty) = module.types.iter()
+        .find(|(_, ty)| ty.name.as_deref() == Some("SimulationConstants"))
+        .unwrap();
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(&module.types, &module.constants).unwrap();
+    assert_eq!(layouter[handle].size as usize, SimulationConstants::SIZE);
+
+    let members = match &ty.inner {
+        naga::TypeInner::Struct { members, .. } => members,
+        inner => panic!("SimulationConstants is a {:?}", inner),
+    };
+    assert_eq!(members.len(), SimulationConstants::MEMBERS.len(), "common.wgsl and constants.rs have different fields");
+
+    // Same names and scalar kinds in the same order, every one 4 bytes and packed
+    for (i, (member, (field, rust_ty))) in members.iter().zip(SimulationConstants::MEMBERS).enumerate() {
+        assert_eq!(member.name.as_deref(), Some(camel_case(field).as_str()), "member {}", i);
+        let kind = match *rust_ty {
+            "f32" => naga::ScalarKind::Float,
+            "u32" => naga::ScalarKind::Uint,
+            "i32" => naga::ScalarKind::Sint,
+            other => panic!("{} is a {}, which the uniform can't hold", field, other),
+        };
+        assert_eq!(module.types[member.ty].inner, naga::TypeInner::Scalar { kind, width: 4 }, "{}", field);
+        assert_eq!(member.offset, i as u32 * 4, "{}", field);
+    }
+    let num_particles = members.iter().find(|m| m.name.as_deref() == Some("numParticles")).unwrap();
+    assert_eq!(num_particles.offset as usize, SimulationConstants::NUM_PARTICLES_OFFSET);
+}
@@ -0,0 +1,62 @@
+//! Parsing sweep ranges and enumerating their combinations, running them needs a GPU.
+
+use std::path::PathBuf;
+
+use particles::sweep::{Range, Sweep};
+use particles::Config;
+
+fn sweep(ranges: &[&str]) -> Sweep {
+    Sweep {
+        base: Config::default(),
+        ranges: ranges.iter().map(|r| r.parse().unwrap()).collect(),
+        steps: 10,
+        metrics_every: 1,
+        dir: PathBuf::from("sweep"),
+    }
+}
+
+#[test]
+fn ranges_parse() {
+    let range: Range = "sense_force=0.1:0.5:5".parse().unwrap();
+    assert_eq!(range.param, "sense_force");
+    assert_eq!(range.values.len(), 5);
+    assert!((range.values[2] - 0.3).abs() < 1e-12);
+    assert_eq!(range.values[4], 0.5);
+
+    let range: Range = "fade_power=0.01,0.02".parse().unwrap();
+    assert_eq!(range.values, vec![0.01, 0.02]);
+    assert_eq!("seed=7:9:1".parse::<Range>().unwrap().values, vec![7.0]);
+
+    assert!("sense_force".parse::<Range>().is_err());
+    assert!("sense_farce=1,2".parse::<Range>().is_err());
+    assert!("sense_force=1:2".parse::<Range>().is_err());
+    assert!("sense_force=0:1:0".parse::<Range>().is_err());
+}
+
+#[test]
+fn combinations_vary_the_last_range_fastest() {
+    let sweep = sweep(&["sense_force=1,2", "fade_power=3,4,5"]);
+    let combinations = sweep.combinations();
+    assert_eq!(combinations.len(), 6);
+    assert_eq!(combinations[0], vec![1.0, 3.0]);
+    assert_eq!(combinations[1], vec![1.0, 4.0]);
+    assert_eq!(combinations[5], vec![2.0, 5.0]);
+
+    let config = sweep.config(&combinations[4]).unwrap();
+    assert_eq!(config.sense_force, 2.0);
+    assert_eq!(config.fade_power, 4.0);
+}
+
+#[test]
+fn invalid_values_are_caught_before_running() {
+    let sweep = sweep(&["map_fidelity=100,150.5"]);
+    assert!(sweep.config(&sweep.combinations()[0]).is_ok());
+    assert!(sweep.config(&sweep.combinations()[1]).is_err());
+}
+
+#[test]
+fn conflicting_settings_are_caught_before_running() {
+    let sweep = Sweep { base: Config { deterministic: true, ..Config::default() }, ..sweep(&["lifecycle=0,1"]) };
+    assert!(sweep.config(&sweep.combinations()[0]).is_ok());
+    assert_eq!(sweep.config(&sweep.combinations()[1]).unwrap_err(), Config { deterministic: true, lifecycle: true, ..Config::default() }.validate().unwrap_err());
+}